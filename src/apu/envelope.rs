@@ -0,0 +1,57 @@
+/// Volume envelope shared by the square and noise channels. Clocked at 64 Hz by the frame
+/// sequencer.
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// Handles a write to NRx2
+    pub fn write(&mut self, val: u8) {
+        self.initial_volume = val >> 4;
+        self.increase = (val & 0x08) != 0;
+        self.period = val & 0x07;
+    }
+
+    /// The DAC is powered by the upper 5 bits of NRx2
+    pub fn dac_enabled(val: u8) -> bool {
+        (val & 0xF8) != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
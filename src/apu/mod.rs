@@ -0,0 +1,377 @@
+mod envelope;
+mod length_counter;
+mod noise;
+mod square;
+mod wave;
+
+use crate::{constants::*, memory::MemoryController};
+
+use self::{noise::NoiseChannel, square::SquareChannel, wave::WaveChannel};
+
+/// Samples per second per stereo channel produced for the host
+pub const SAMPLE_RATE: u64 = 48000;
+
+/// Bits that always read back as 1 for each register from NR10 to NR52. Unused registers read
+/// as 0xFF.
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41 - NR44
+    0x00, 0x00, 0x70, // NR50 - NR52
+];
+
+/// Bits that read back as 1 from an APU register no matter what was written
+pub fn register_read_mask(addr: u16) -> u8 {
+    READ_MASKS[(addr - ADDRESS_NR10) as usize]
+}
+
+// https://gbdev.io/pandocs/Audio.html
+pub struct Apu {
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+    powered: bool,
+    nr50: u8,
+    nr51: u8,
+    frame_sequencer_step: u8,
+    /// DIV bit 4 (bit 5 in double speed) from the previous cycle. The frame sequencer is clocked
+    /// on its falling edge.
+    last_div_bit: bool,
+    last_nr52: u8,
+    /// Counts up by the sample rate every T-cycle, a sample is taken each time it passes the
+    /// clock rate
+    sample_timer: u64,
+    /// Charge of the high-pass filter capacitors that remove the DC offset from the DACs
+    capacitors: (f32, f32),
+    capacitor_charge_factor: f32,
+    /// Interleaved left and right samples waiting to be played
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            powered: false,
+            nr50: 0,
+            nr51: 0,
+            frame_sequencer_step: 0,
+            last_div_bit: false,
+            last_nr52: 0,
+            sample_timer: 0,
+            capacitors: (0., 0.),
+            capacitor_charge_factor: 0.999958_f32
+                .powf((T_CYCLES_PER_SECOND / SAMPLE_RATE) as f32),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Runs the APU for a single T-cycle
+    pub fn step(&mut self, mem: &mut dyn MemoryController) {
+        if !mem.shared_data().apu_writes.is_empty() {
+            let writes = std::mem::take(&mut mem.shared_data_mut().apu_writes);
+            for (addr, val) in writes {
+                self.write_register(mem, addr, val);
+            }
+        }
+
+        // DIV counts twice as fast in double speed, so a higher bit keeps the sequencer at 512 Hz
+        let div_mask = if mem.shared_data().double_speed { 0x20 } else { 0x10 };
+        let div_bit = (mem.read_8_sys(ADDRESS_DIV) & div_mask) != 0;
+        if self.last_div_bit && !div_bit && self.powered {
+            self.clock_frame_sequencer();
+        }
+        self.last_div_bit = div_bit;
+
+        if self.powered {
+            self.ch1.step();
+            self.ch2.step();
+            self.ch3.step(mem);
+            self.ch4.step();
+        }
+
+        let nr52 = self.nr52();
+        if nr52 != self.last_nr52 {
+            mem.write_8_sys(ADDRESS_NR52, nr52);
+            self.last_nr52 = nr52;
+        }
+
+        self.sample_timer += SAMPLE_RATE;
+        if self.sample_timer >= T_CYCLES_PER_SECOND {
+            self.sample_timer -= T_CYCLES_PER_SECOND;
+            let (left, right) = self.mix();
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    /// Samples produced since the last call, interleaved left then right
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn nr52(&self) -> u8 {
+        let mut nr52 = register_read_mask(ADDRESS_NR52);
+        if self.powered {
+            nr52 |= 0x80;
+        }
+        let channels_enabled = [self.ch1.enabled, self.ch2.enabled, self.ch3.enabled, self.ch4.enabled];
+        for (i, enabled) in channels_enabled.into_iter().enumerate() {
+            if enabled {
+                nr52 |= 1 << i;
+            }
+        }
+        nr52
+    }
+
+    fn write_register(&mut self, mem: &mut dyn MemoryController, addr: u16, val: u8) {
+        if addr == ADDRESS_NR52 {
+            let power = (val & 0x80) != 0;
+            if self.powered && !power {
+                self.power_off(mem);
+            } else if !self.powered && power {
+                self.frame_sequencer_step = 0;
+            }
+            self.powered = power;
+            return;
+        }
+
+        if !self.powered {
+            return;
+        }
+
+        match addr {
+            ADDRESS_NR10 => self.ch1.write_sweep(val),
+            ADDRESS_NR11 => self.ch1.write_length_duty(val),
+            ADDRESS_NR12 => self.ch1.write_envelope(val),
+            ADDRESS_NR13 => self.ch1.write_frequency_low(val),
+            ADDRESS_NR14 => self.ch1.write_control(val),
+            ADDRESS_NR21 => self.ch2.write_length_duty(val),
+            ADDRESS_NR22 => self.ch2.write_envelope(val),
+            ADDRESS_NR23 => self.ch2.write_frequency_low(val),
+            ADDRESS_NR24 => self.ch2.write_control(val),
+            ADDRESS_NR30 => self.ch3.write_dac(val),
+            ADDRESS_NR31 => self.ch3.write_length(val),
+            ADDRESS_NR32 => self.ch3.write_output_level(val),
+            ADDRESS_NR33 => self.ch3.write_frequency_low(val),
+            ADDRESS_NR34 => self.ch3.write_control(val),
+            ADDRESS_NR41 => self.ch4.write_length(val),
+            ADDRESS_NR42 => self.ch4.write_envelope(val),
+            ADDRESS_NR43 => self.ch4.write_polynomial(val),
+            ADDRESS_NR44 => self.ch4.write_control(val),
+            ADDRESS_NR50 => self.nr50 = val,
+            ADDRESS_NR51 => self.nr51 = val,
+            _ => {}
+        }
+    }
+
+    /// Turning the APU off clears every register except wave RAM
+    fn power_off(&mut self, mem: &mut dyn MemoryController) {
+        self.ch1 = SquareChannel::new(true);
+        self.ch2 = SquareChannel::new(false);
+        self.ch3 = WaveChannel::new();
+        self.ch4 = NoiseChannel::new();
+        self.nr50 = 0;
+        self.nr51 = 0;
+
+        for addr in ADDRESS_NR10..ADDRESS_NR52 {
+            mem.write_8_sys(addr, register_read_mask(addr));
+        }
+    }
+
+    /// Clocked at 512 Hz. Lengths are clocked at 256 Hz, sweep at 128 Hz, and envelopes at 64 Hz.
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        if step.is_multiple_of(2) {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if step == 7 {
+            self.ch1.envelope.clock();
+            self.ch2.envelope.clock();
+            self.ch4.envelope.clock();
+        }
+
+        self.frame_sequencer_step = (step + 1) % 8;
+    }
+
+    /// Mixes the channels through NR51 panning and NR50 master volume
+    fn mix(&mut self) -> (f32, f32) {
+        if !self.powered {
+            return (0., 0.);
+        }
+
+        let outputs = [
+            dac_output(self.ch1.output(), self.ch1.dac_enabled),
+            dac_output(self.ch2.output(), self.ch2.dac_enabled),
+            dac_output(self.ch3.output(), self.ch3.dac_enabled),
+            dac_output(self.ch4.output(), self.ch4.dac_enabled),
+        ];
+
+        let mut left = 0.;
+        let mut right = 0.;
+        for (i, output) in outputs.into_iter().enumerate() {
+            if (self.nr51 & (0x10 << i)) != 0 {
+                left += output;
+            }
+            if (self.nr51 & (1 << i)) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = (((self.nr50 >> 4) & 7) + 1) as f32 / 8.;
+        let right_volume = ((self.nr50 & 7) + 1) as f32 / 8.;
+        let left = left / 4. * left_volume;
+        let right = right / 4. * right_volume;
+
+        (
+            high_pass(&mut self.capacitors.0, left, self.capacitor_charge_factor),
+            high_pass(&mut self.capacitors.1, right, self.capacitor_charge_factor),
+        )
+    }
+}
+
+/// Converts a digital channel output from 0 to 15 into an analog value from -1 to 1
+fn dac_output(digital: u8, dac_enabled: bool) -> f32 {
+    if dac_enabled {
+        digital as f32 / 7.5 - 1.
+    } else {
+        0.
+    }
+}
+
+fn high_pass(capacitor: &mut f32, input: f32, charge_factor: f32) -> f32 {
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::*, debug::metrics::DebugMetrics, memory::MemoryController, memory_controllers::basic_memory::BasicMemory, opcodes::process_instruction, timer::Timer};
+
+    use super::Apu;
+
+    fn step_n(apu: &mut Apu, timer: &mut Timer, mem: &mut dyn MemoryController, cycles: u32) {
+        for _ in 0..cycles {
+            timer.step(mem);
+            apu.step(mem);
+        }
+    }
+
+    fn powered_on() -> (Apu, Timer, BasicMemory) {
+        let mut m = BasicMemory::default();
+        let mut apu = Apu::new();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_NR52, 0x80);
+        step_n(&mut apu, &mut timer, &mut m, 1);
+        (apu, timer, m)
+    }
+
+    #[test]
+    fn trigger_enables_channel_in_nr52() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        assert_eq!(0xF0, m.read_8(ADDRESS_NR52));
+
+        m.write_8(ADDRESS_NR22, 0xF0);
+        m.write_8(ADDRESS_NR24, 0x80);
+        step_n(&mut apu, &mut timer, &mut m, 1);
+        assert_eq!(0xF2, m.read_8(ADDRESS_NR52));
+    }
+
+    #[test]
+    fn trigger_through_hl() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        m.write_8(ADDRESS_NR22, 0xF0);
+        // LD (HL),A
+        m.write_8(0xC000, 0b01_110_111);
+        m.r().pc = 0xC000;
+        m.r().a = 0x80;
+        m.r().hl.s16(ADDRESS_NR24);
+
+        process_instruction(&mut m, &mut DebugMetrics::new());
+        step_n(&mut apu, &mut timer, &mut m, 1);
+        assert_eq!(0xF2, m.read_8(ADDRESS_NR52));
+    }
+
+    #[test]
+    fn trigger_without_dac_leaves_channel_off() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        m.write_8(ADDRESS_NR12, 0x00);
+        m.write_8(ADDRESS_NR14, 0x80);
+        step_n(&mut apu, &mut timer, &mut m, 1);
+        assert_eq!(0xF0, m.read_8(ADDRESS_NR52));
+    }
+
+    #[test]
+    fn length_counter_disables_channel() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        m.write_8(ADDRESS_NR42, 0xF0);
+        // 2 steps of length left
+        m.write_8(ADDRESS_NR41, 62);
+        m.write_8(ADDRESS_NR44, 0xC0);
+
+        // Lengths are clocked every 2 frame sequencer steps, which are 8192 T-cycles apart
+        step_n(&mut apu, &mut timer, &mut m, 8192 * 2);
+        assert_eq!(0xF8, m.read_8(ADDRESS_NR52));
+        step_n(&mut apu, &mut timer, &mut m, 8192 * 2);
+        assert_eq!(0xF0, m.read_8(ADDRESS_NR52));
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        m.write_8(ADDRESS_NR10, 0x01);
+        m.write_8(ADDRESS_NR12, 0xF0);
+        m.write_8(ADDRESS_NR13, 0xFF);
+        m.write_8(ADDRESS_NR14, 0x87);
+        step_n(&mut apu, &mut timer, &mut m, 1);
+        assert_eq!(0xF0, m.read_8(ADDRESS_NR52));
+    }
+
+    #[test]
+    fn registers_read_back_with_mask() {
+        let (_, _, mut m) = powered_on();
+        m.write_8(ADDRESS_NR11, 0x80);
+        m.write_8(ADDRESS_NR13, 0x12);
+        m.write_8(ADDRESS_NR50, 0x77);
+
+        assert_eq!(0xBF, m.read_8(ADDRESS_NR11));
+        assert_eq!(0xFF, m.read_8(ADDRESS_NR13));
+        assert_eq!(0x77, m.read_8(ADDRESS_NR50));
+    }
+
+    #[test]
+    fn power_off_clears_registers_and_ignores_writes() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        m.write_8(ADDRESS_NR50, 0x77);
+        m.write_8(ADDRESS_WAVE_RAM_START, 0x12);
+        m.write_8(ADDRESS_NR52, 0x00);
+        step_n(&mut apu, &mut timer, &mut m, 1);
+
+        assert_eq!(0x00, m.read_8(ADDRESS_NR50));
+        assert_eq!(0x70, m.read_8(ADDRESS_NR52));
+        assert_eq!(0x12, m.read_8(ADDRESS_WAVE_RAM_START));
+
+        m.write_8(ADDRESS_NR50, 0x77);
+        assert_eq!(0x00, m.read_8(ADDRESS_NR50));
+    }
+
+    #[test]
+    fn produces_samples_at_sample_rate() {
+        let (mut apu, mut timer, mut m) = powered_on();
+        apu.take_samples();
+        step_n(&mut apu, &mut timer, &mut m, T_CYCLES_PER_SECOND as u32 / 100);
+        assert_eq!(2 * 480, apu.take_samples().len());
+    }
+}
@@ -0,0 +1,102 @@
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise
+pub struct NoiseChannel {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    clock_shift: u8,
+    /// Also feed the LFSR output back into bit 6, giving a shorter and more tonal pattern
+    short_mode: bool,
+    divisor_code: u8,
+    timer: u32,
+    /// 15 bit linear feedback shift register
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0x3F);
+    }
+
+    pub fn write_envelope(&mut self, val: u8) {
+        self.envelope.write(val);
+        self.dac_enabled = Envelope::dac_enabled(val);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_polynomial(&mut self, val: u8) {
+        self.clock_shift = val >> 4;
+        self.short_mode = (val & 0x08) != 0;
+        self.divisor_code = val & 0x07;
+    }
+
+    pub fn write_control(&mut self, val: u8) {
+        self.length.enabled = (val & 0x40) != 0;
+        if (val & 0x80) != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = self.period();
+            self.envelope.trigger();
+            self.lfsr = 0x7FFF;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    /// Runs the channel for a single T-cycle
+    pub fn step(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period();
+        // Shifts of 14 and 15 don't clock the LFSR at all
+        if self.clock_shift >= 14 {
+            return;
+        }
+
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Current output level from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled || (self.lfsr & 1) != 0 {
+            return 0;
+        }
+
+        self.envelope.volume
+    }
+}
@@ -0,0 +1,181 @@
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+/// Waveforms for the four duty cycles, one bit per step
+const DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
+
+/// Frequency sweep, only present on channel 1
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow_frequency: 0,
+        }
+    }
+
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.negate {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8 by the timer
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+// https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-1--pulse-with-period-sweep
+pub struct SquareChannel {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    /// 11 bit period value from NRx3 and NRx4
+    frequency: u16,
+    timer: u32,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> Self {
+        SquareChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_position: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            timer: 0,
+            sweep: if has_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    pub fn write_sweep(&mut self, val: u8) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.period = (val >> 4) & 0x07;
+            sweep.negate = (val & 0x08) != 0;
+            sweep.shift = val & 0x07;
+        }
+    }
+
+    pub fn write_length_duty(&mut self, val: u8) {
+        self.duty = val >> 6;
+        self.length.load(val & 0x3F);
+    }
+
+    pub fn write_envelope(&mut self, val: u8) {
+        self.envelope.write(val);
+        self.dac_enabled = Envelope::dac_enabled(val);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_frequency_low(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x700) | val as u16;
+    }
+
+    pub fn write_control(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+        self.length.enabled = (val & 0x40) != 0;
+        if (val & 0x80) != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow_frequency = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            // The overflow check happens immediately when shift is non-zero
+            if sweep.shift != 0 && sweep.next_frequency() > 0x7FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    /// Runs the channel for a single T-cycle
+    pub fn step(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+        } else {
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) & 7;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        let frequency = sweep.next_frequency();
+        if frequency > 0x7FF {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow_frequency = frequency;
+            self.frequency = frequency;
+            // The new frequency is checked again but not used
+            if sweep.next_frequency() > 0x7FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Current output level from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_position)) & 1;
+        high * self.envelope.volume
+    }
+}
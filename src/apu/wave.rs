@@ -0,0 +1,100 @@
+use crate::{constants::ADDRESS_WAVE_RAM_START, memory::MemoryController};
+
+use super::length_counter::LengthCounter;
+
+// https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-3--wave-output
+pub struct WaveChannel {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    pub length: LengthCounter,
+    /// Right shift applied to samples for the NR32 output levels: mute, 100%, 50%, 25%
+    volume_shift: u8,
+    frequency: u16,
+    timer: u32,
+    /// Index of the current 4 bit sample in wave RAM
+    position: u8,
+    sample_buffer: u8,
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume_shift: 4,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+        }
+    }
+
+    pub fn write_dac(&mut self, val: u8) {
+        self.dac_enabled = (val & 0x80) != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    pub fn write_output_level(&mut self, val: u8) {
+        self.volume_shift = match (val >> 5) & 3 {
+            0 => 4,
+            1 => 0,
+            2 => 1,
+            _ => 2,
+        };
+    }
+
+    pub fn write_frequency_low(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0x700) | val as u16;
+    }
+
+    pub fn write_control(&mut self, val: u8) {
+        self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+        self.length.enabled = (val & 0x40) != 0;
+        if (val & 0x80) != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = self.period();
+            self.position = 0;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    /// Runs the channel for a single T-cycle
+    pub fn step(&mut self, mem: &dyn MemoryController) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period();
+        self.position = (self.position + 1) & 31;
+        // Samples are packed two per byte, high nibble first
+        let byte = mem.read_8_sys(ADDRESS_WAVE_RAM_START + (self.position / 2) as u16);
+        self.sample_buffer = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Current output level from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        self.sample_buffer >> self.volume_shift
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait}, BufferSize, SampleRate, Stream, StreamConfig
+};
+
+use crate::apu::SAMPLE_RATE;
+
+/// 100ms of interleaved stereo samples. Anything queued beyond this is dropped so the sound
+/// can't drift further and further behind the picture.
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize / 10 * 2;
+
+/// Plays samples from the APU on the default output device.
+/// The emulator pushes samples once per frame and the device pulls them from a bounded buffer
+/// on its own thread, playing silence if it runs dry.
+pub struct AudioOutput {
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    /// Sound stops when this is dropped. `None` if there's no usable output device.
+    stream: Option<Stream>,
+}
+
+impl AudioOutput {
+    pub fn new() -> Self {
+        let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED_SAMPLES)));
+        let stream = open_stream(buffer.clone()).map_err(|err| println!("Failed to open audio output, sound is disabled: {}", err)).ok();
+        AudioOutput { buffer, stream }
+    }
+
+    /// Queues interleaved stereo samples for playback
+    pub fn play(&mut self, samples: Vec<f32>) {
+        if self.stream.is_none() {
+            return;
+        }
+        queue_samples(&mut self.buffer.lock().unwrap(), samples);
+    }
+}
+
+fn open_stream(buffer: Arc<Mutex<VecDeque<f32>>>) -> Result<Stream, String> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("no output device")?;
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(SAMPLE_RATE as u32),
+        buffer_size: BufferSize::Default,
+    };
+
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                let mut buffer = buffer.lock().unwrap();
+                for sample in data.iter_mut() {
+                    *sample = buffer.pop_front().unwrap_or(0.);
+                }
+            },
+            |err| println!("Audio output error: {}", err),
+            None,
+        )
+        .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok(stream)
+}
+
+/// Adds `samples` to the end of the buffer, dropping the oldest ones if it's over the limit
+fn queue_samples(buffer: &mut VecDeque<f32>, samples: Vec<f32>) {
+    buffer.extend(samples);
+    let excess = buffer.len().saturating_sub(MAX_BUFFERED_SAMPLES);
+    buffer.drain(..excess);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::{queue_samples, MAX_BUFFERED_SAMPLES};
+
+    #[test]
+    fn drops_oldest_samples_when_full() {
+        let mut buffer = VecDeque::new();
+        queue_samples(&mut buffer, vec![0.; MAX_BUFFERED_SAMPLES - 2]);
+        queue_samples(&mut buffer, vec![1.; 6]);
+
+        assert_eq!(MAX_BUFFERED_SAMPLES, buffer.len());
+        assert_eq!(Some(&1.), buffer.back());
+        assert_eq!(6, buffer.iter().filter(|sample| **sample == 1.).count());
+    }
+}
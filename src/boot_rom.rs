@@ -0,0 +1,171 @@
+use crate::{constants::*, memory::{MemoryController, RegisterFlags}};
+
+/// The DMG boot ROM is mapped over 0x0000 - 0x00FF
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+/// IO registers as the DMG boot ROM leaves them, other than the sound registers
+const POST_BOOT_IO_REGISTERS: [(u16, u8); 19] = [
+    (ADDRESS_JOYP, 0xCF),
+    (ADDRESS_SB, 0x00),
+    (ADDRESS_SC, 0x7E),
+    (ADDRESS_DIV, 0xAB),
+    (ADDRESS_TIMA, 0x00),
+    (ADDRESS_TMA, 0x00),
+    (ADDRESS_TAC, 0xF8),
+    (ADDRESS_IF, 0xE1),
+    (ADDRESS_LCDC, 0x91),
+    (ADDRESS_STAT, 0x85),
+    (ADDRESS_SCY, 0x00),
+    (ADDRESS_SCX, 0x00),
+    (ADDRESS_LY, 0x00),
+    (ADDRESS_LYC, 0x00),
+    (ADDRESS_DMA_CONTROL, 0xFF),
+    (ADDRESS_BGP, 0xFC),
+    (ADDRESS_WY, 0x00),
+    (ADDRESS_WX, 0x00),
+    (ADDRESS_IE, 0x00),
+];
+
+/// CGB only registers as the CGB boot ROM leaves them
+const POST_BOOT_CGB_REGISTERS: [(u16, u8); 5] = [
+    (ADDRESS_KEY1, 0x7E),
+    (ADDRESS_VBK, 0xFE),
+    (ADDRESS_SVBK, 0xF8),
+    (ADDRESS_BCPS, 0x40),
+    (ADDRESS_OCPS, 0x40),
+];
+
+/// Sound registers as the DMG boot ROM leaves them, in the order they're written. The APU has to
+/// be powered on first or the rest are ignored.
+const POST_BOOT_SOUND_REGISTERS: [(u16, u8); 21] = [
+    (ADDRESS_NR52, 0xF1),
+    (ADDRESS_NR10, 0x80),
+    (ADDRESS_NR11, 0xBF),
+    (ADDRESS_NR12, 0xF3),
+    (ADDRESS_NR13, 0xFF),
+    (ADDRESS_NR14, 0xBF),
+    (ADDRESS_NR21, 0x3F),
+    (ADDRESS_NR22, 0x00),
+    (ADDRESS_NR23, 0xFF),
+    (ADDRESS_NR24, 0xBF),
+    (ADDRESS_NR30, 0x7F),
+    (ADDRESS_NR31, 0xFF),
+    (ADDRESS_NR32, 0x9F),
+    (ADDRESS_NR33, 0xFF),
+    (ADDRESS_NR34, 0xBF),
+    (ADDRESS_NR41, 0xFF),
+    (ADDRESS_NR42, 0x00),
+    (ADDRESS_NR43, 0x00),
+    (ADDRESS_NR44, 0xBF),
+    (ADDRESS_NR50, 0x77),
+    (ADDRESS_NR51, 0xF3),
+];
+
+// https://gbdev.io/pandocs/Power_Up_Sequence.html
+/// Sets up the CPU and IO registers the way the DMG boot ROM, or the CGB one in CGB mode, leaves
+/// them when it jumps to the cartridge at 0x0100. `header_checksum` is the byte at 0x014D, which
+/// the DMG's H and C flags depend on.
+pub fn set_post_boot_state(mem: &mut dyn MemoryController, header_checksum: u8) {
+    let cgb_mode = mem.shared_data().cgb_mode;
+    let r = mem.r();
+    if cgb_mode {
+        // A = 0x11 is how games detect they're running on a CGB
+        r.a = 0x11;
+        r.f = RegisterFlags::Z;
+        r.bc.s16(0x0000);
+        r.de.s16(0xFF56);
+        r.hl.s16(0x000D);
+    } else {
+        r.a = 0x01;
+        r.f = RegisterFlags::Z;
+        if header_checksum != 0 {
+            r.f |= RegisterFlags::H | RegisterFlags::CY;
+        }
+        r.bc.s16(0x0013);
+        r.de.s16(0x00D8);
+        r.hl.s16(0x014D);
+    }
+    r.sp = ADDRESS_STACK_START;
+    r.pc = 0x0100;
+    *mem.ime() = false;
+
+    for (addr, val) in POST_BOOT_IO_REGISTERS {
+        mem.write_8_sys(addr, val);
+    }
+    if cgb_mode {
+        for (addr, val) in POST_BOOT_CGB_REGISTERS {
+            mem.write_8_sys(addr, val);
+        }
+    }
+    mem.process_input();
+
+    for (addr, val) in POST_BOOT_SOUND_REGISTERS {
+        // The boot sound has already been triggered, doing it again would replay the end of it
+        let val = if matches!(addr, ADDRESS_NR14 | ADDRESS_NR24 | ADDRESS_NR34 | ADDRESS_NR44) {
+            val & 0x7F
+        } else {
+            val
+        };
+        mem.write_8(addr, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        constants::*,
+        memory::{MemoryController, RegisterFlags},
+        memory_controllers::basic_memory::BasicMemory,
+    };
+
+    use super::{set_post_boot_state, BOOT_ROM_SIZE};
+
+    #[test]
+    fn boot_rom_mapped_until_disabled() {
+        let mut m = BasicMemory::new(vec![0x11; 0x8000], false);
+        m.shared_data_mut().boot_rom = Some(vec![0x22; BOOT_ROM_SIZE]);
+
+        assert_eq!(0x22, m.read_8(0x0000));
+        assert_eq!(0x22, m.read_8(0x00FF));
+        assert_eq!(0x11, m.read_8(0x0100));
+
+        m.write_8(ADDRESS_BOOT_ROM_DISABLE, 0x01);
+        assert_eq!(0x11, m.read_8(0x0000));
+    }
+
+    #[test]
+    fn post_boot_state() {
+        let mut m = BasicMemory::default();
+        set_post_boot_state(&mut m, 0x12);
+
+        assert_eq!(0x01, m.r_i().a);
+        assert_eq!(RegisterFlags::Z | RegisterFlags::H | RegisterFlags::CY, m.r_i().f);
+        assert_eq!(0x014D, m.r_i().hl.r16());
+        assert_eq!(0x0100, m.r_i().pc);
+        assert_eq!(0xFFFE, m.r_i().sp);
+        assert_eq!(0xCF, m.read_8(ADDRESS_JOYP));
+        assert_eq!(0xAB, m.read_8(ADDRESS_DIV));
+        assert_eq!(0x91, m.read_8(ADDRESS_LCDC));
+        assert_eq!(0xBF, m.read_8(ADDRESS_NR14));
+        assert_eq!(0x77, m.read_8(ADDRESS_NR50));
+        assert_eq!(0x80, m.read_8(ADDRESS_NR52) & 0x80);
+    }
+
+    #[test]
+    fn post_boot_state_cgb() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().cgb_mode = true;
+        set_post_boot_state(&mut m, 0x12);
+
+        assert_eq!(0x11, m.r_i().a);
+        assert_eq!(RegisterFlags::Z, m.r_i().f);
+        assert_eq!(0xFE, m.read_8(ADDRESS_VBK));
+    }
+
+    #[test]
+    fn post_boot_flags_depend_on_header_checksum() {
+        let mut m = BasicMemory::default();
+        set_post_boot_state(&mut m, 0x00);
+        assert_eq!(RegisterFlags::Z, m.r_i().f);
+    }
+}
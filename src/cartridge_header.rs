@@ -0,0 +1,394 @@
+use std::fmt::Display;
+
+use crate::constants::*;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbcKind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mbc: MbcKind,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub has_timer: bool,
+    pub has_rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        // (mbc, ram, battery, timer, rumble)
+        let (mbc, has_ram, has_battery, has_timer, has_rumble) = match code {
+            0x00 => (MbcKind::None, false, false, false, false),
+            0x01 => (MbcKind::Mbc1, false, false, false, false),
+            0x02 => (MbcKind::Mbc1, true, false, false, false),
+            0x03 => (MbcKind::Mbc1, true, true, false, false),
+            0x05 => (MbcKind::Mbc2, false, false, false, false),
+            0x06 => (MbcKind::Mbc2, false, true, false, false),
+            0x08 => (MbcKind::None, true, false, false, false),
+            0x09 => (MbcKind::None, true, true, false, false),
+            0x0B => (MbcKind::Mmm01, false, false, false, false),
+            0x0C => (MbcKind::Mmm01, true, false, false, false),
+            0x0D => (MbcKind::Mmm01, true, true, false, false),
+            0x0F => (MbcKind::Mbc3, false, true, true, false),
+            0x10 => (MbcKind::Mbc3, true, true, true, false),
+            0x11 => (MbcKind::Mbc3, false, false, false, false),
+            0x12 => (MbcKind::Mbc3, true, false, false, false),
+            0x13 => (MbcKind::Mbc3, true, true, false, false),
+            0x19 => (MbcKind::Mbc5, false, false, false, false),
+            0x1A => (MbcKind::Mbc5, true, false, false, false),
+            0x1B => (MbcKind::Mbc5, true, true, false, false),
+            0x1C => (MbcKind::Mbc5, false, false, false, true),
+            0x1D => (MbcKind::Mbc5, true, false, false, true),
+            0x1E => (MbcKind::Mbc5, true, true, false, true),
+            0x20 => (MbcKind::Mbc6, false, false, false, false),
+            0x22 => (MbcKind::Mbc7, true, true, false, true),
+            0xFC => (MbcKind::PocketCamera, false, false, false, false),
+            0xFD => (MbcKind::Tama5, false, false, false, false),
+            0xFE => (MbcKind::HuC3, false, false, false, false),
+            0xFF => (MbcKind::HuC1, true, true, false, false),
+            _ => return None,
+        };
+
+        Some(CartridgeType {
+            code,
+            mbc,
+            has_ram,
+            has_battery,
+            has_timer,
+            has_rumble,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    DmgOnly,
+    /// Works on DMG but has CGB enhancements
+    Enhanced,
+    CgbOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Licensee {
+    /// Single byte code at 0x014B
+    Old(u8),
+    /// Two ASCII characters at 0x0144 - 0x0145, used when the old code is 0x33
+    New([u8; 2]),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeHeaderError {
+    /// The file ends before the header does
+    Truncated { len: usize },
+    UnknownCartridgeType(u8),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    /// The header declares more ROM than the file contains
+    RomSizeMismatch { declared: usize, actual: usize },
+    /// A known cartridge type whose hardware isn't emulated
+    UnsupportedMbc(MbcKind),
+}
+
+impl Display for CartridgeHeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeHeaderError::Truncated { len } => write!(
+                f,
+                "ROM is {len:#x} bytes long but the header ends at {:#x}",
+                ADDRESS_GLOBAL_CHECKSUM + 2
+            ),
+            CartridgeHeaderError::UnknownCartridgeType(code) => {
+                write!(f, "Unknown cartridge type {code:#04x}")
+            }
+            CartridgeHeaderError::InvalidRomSize(code) => write!(f, "Invalid ROM size code {code:#04x}"),
+            CartridgeHeaderError::InvalidRamSize(code) => write!(f, "Invalid RAM size code {code:#04x}"),
+            CartridgeHeaderError::RomSizeMismatch { declared, actual } => write!(
+                f,
+                "Header declares {declared:#x} bytes of ROM but the file is {actual:#x} bytes"
+            ),
+            CartridgeHeaderError::UnsupportedMbc(mbc) => write!(f, "{mbc:?} cartridges aren't supported yet"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only present on later cartridges, where it takes the last 4 bytes of the title area
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    /// false for Japan, true for everywhere else
+    pub overseas_only: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+}
+
+/// RAM size in bytes for the code at 0x0149
+pub fn ram_size_from_code(code: u8) -> Option<usize> {
+    match code {
+        0 => Some(0),
+        // unofficial 2 KiB size listed by pandocs, only ever used by homebrew
+        1 => Some(0x800),
+        2 => Some(0x2000),
+        3 => Some(0x8000),
+        4 => Some(0x20000),
+        5 => Some(0x10000),
+        _ => None,
+    }
+}
+
+/// ROM size in bytes for the code at 0x0148
+pub fn rom_size_from_code(code: u8) -> Option<usize> {
+    if code <= 8 {
+        Some(0x8000 << code)
+    } else {
+        None
+    }
+}
+
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[ADDRESS_TITLE as usize..ADDRESS_HEADER_CHECKSUM as usize]
+        .iter()
+        .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1))
+}
+
+/// Sum of every byte in the ROM except the global checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    let checksum_range = ADDRESS_GLOBAL_CHECKSUM as usize..ADDRESS_GLOBAL_CHECKSUM as usize + 2;
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| !checksum_range.contains(i))
+        .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16))
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeHeaderError> {
+        if rom.len() < ADDRESS_GLOBAL_CHECKSUM as usize + 2 {
+            return Err(CartridgeHeaderError::Truncated { len: rom.len() });
+        }
+
+        let byte = |addr: u16| rom[addr as usize];
+
+        let cartridge_type = CartridgeType::from_code(byte(ADDRESS_CARTRIDGE_TYPE))
+            .ok_or(CartridgeHeaderError::UnknownCartridgeType(byte(ADDRESS_CARTRIDGE_TYPE)))?;
+        let rom_size = rom_size_from_code(byte(ADDRESS_ROM_SIZE))
+            .ok_or(CartridgeHeaderError::InvalidRomSize(byte(ADDRESS_ROM_SIZE)))?;
+        let ram_size = if cartridge_type.mbc == MbcKind::Mbc2 {
+            // MBC2 has its RAM built in and the header should declare none
+            0
+        } else {
+            ram_size_from_code(byte(ADDRESS_RAM_SIZE))
+                .ok_or(CartridgeHeaderError::InvalidRamSize(byte(ADDRESS_RAM_SIZE)))?
+        };
+
+        if rom_size > rom.len() {
+            return Err(CartridgeHeaderError::RomSizeMismatch {
+                declared: rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        let cgb_flag = byte(ADDRESS_CGB_FLAG);
+        let cgb_support = match cgb_flag {
+            0x80 => CgbSupport::Enhanced,
+            0xC0 => CgbSupport::CgbOnly,
+            _ => CgbSupport::DmgOnly,
+        };
+
+        // Cartridges that use the CGB flag shortened the title, newer ones also fit a
+        // manufacturer code in before it
+        let manufacturer_bytes = &rom[ADDRESS_MANUFACTURER_CODE as usize..ADDRESS_CGB_FLAG as usize];
+        let manufacturer_code = if cgb_support != CgbSupport::DmgOnly
+            && manufacturer_bytes.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            Some(String::from_utf8_lossy(manufacturer_bytes).into_owned())
+        } else {
+            None
+        };
+        let title_end = if manufacturer_code.is_some() {
+            ADDRESS_MANUFACTURER_CODE
+        } else if cgb_support != CgbSupport::DmgOnly {
+            ADDRESS_CGB_FLAG
+        } else {
+            ADDRESS_CGB_FLAG + 1
+        };
+        let title = rom[ADDRESS_TITLE as usize..title_end as usize]
+            .iter()
+            .take_while(|b| **b != 0)
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '?' })
+            .collect();
+
+        let licensee = match byte(ADDRESS_OLD_LICENSEE) {
+            0x33 => Licensee::New([byte(ADDRESS_NEW_LICENSEE), byte(ADDRESS_NEW_LICENSEE + 1)]),
+            code => Licensee::Old(code),
+        };
+
+        let header_checksum = byte(ADDRESS_HEADER_CHECKSUM);
+        let global_checksum = u16::from_be_bytes([
+            byte(ADDRESS_GLOBAL_CHECKSUM),
+            byte(ADDRESS_GLOBAL_CHECKSUM + 1),
+        ]);
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_support,
+            // The SGB flag is only honored when the old licensee code is 0x33
+            sgb_support: byte(ADDRESS_SGB_FLAG) == 0x03 && byte(ADDRESS_OLD_LICENSEE) == 0x33,
+            licensee,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            overseas_only: byte(ADDRESS_DESTINATION_CODE) != 0,
+            version: byte(ADDRESS_ROM_VERSION),
+            header_checksum,
+            header_checksum_valid: header_checksum == self::header_checksum(rom),
+            global_checksum,
+            global_checksum_valid: global_checksum == self::global_checksum(rom),
+        })
+    }
+}
+
+impl Display for CartridgeHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let check = |valid: bool| if valid { "ok" } else { "MISMATCH" };
+
+        writeln!(f, "Title: {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer code: {}", code)?;
+        }
+        writeln!(f, "CGB support: {:?}, SGB support: {}", self.cgb_support, self.sgb_support)?;
+        match self.licensee {
+            Licensee::Old(code) => writeln!(f, "Licensee: {:#04x}", code)?,
+            Licensee::New(code) => writeln!(f, "Licensee: {}", String::from_utf8_lossy(&code))?,
+        }
+        writeln!(
+            f,
+            "Cartridge type: {:#04x} ({:?}, ram: {}, battery: {}, timer: {}, rumble: {})",
+            self.cartridge_type.code,
+            self.cartridge_type.mbc,
+            self.cartridge_type.has_ram,
+            self.cartridge_type.has_battery,
+            self.cartridge_type.has_timer,
+            self.cartridge_type.has_rumble
+        )?;
+        writeln!(f, "ROM size: {:#x}, RAM size: {:#x}", self.rom_size, self.ram_size)?;
+        writeln!(
+            f,
+            "Destination: {}, version: {}",
+            if self.overseas_only { "overseas" } else { "Japan" },
+            self.version
+        )?;
+        writeln!(
+            f,
+            "Header checksum: {:#04x} ({})",
+            self.header_checksum,
+            check(self.header_checksum_valid)
+        )?;
+        write!(
+            f,
+            "Global checksum: {:#06x} ({})",
+            self.global_checksum,
+            check(self.global_checksum_valid)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::*;
+
+    use super::{
+        global_checksum, header_checksum, CartridgeHeader, CartridgeHeaderError, CgbSupport,
+        Licensee, MbcKind,
+    };
+
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[ADDRESS_TITLE as usize..ADDRESS_TITLE as usize + 6].copy_from_slice(b"TETRIS");
+        rom[ADDRESS_CARTRIDGE_TYPE as usize] = 0x13;
+        rom[ADDRESS_ROM_SIZE as usize] = 1;
+        rom[ADDRESS_RAM_SIZE as usize] = 3;
+        rom[ADDRESS_OLD_LICENSEE as usize] = 0x01;
+        rom[ADDRESS_HEADER_CHECKSUM as usize] = header_checksum(&rom);
+        let global = global_checksum(&rom).to_be_bytes();
+        rom[ADDRESS_GLOBAL_CHECKSUM as usize] = global[0];
+        rom[ADDRESS_GLOBAL_CHECKSUM as usize + 1] = global[1];
+        rom
+    }
+
+    #[test]
+    fn parses_valid_header() {
+        let header = CartridgeHeader::parse(&valid_rom()).unwrap();
+
+        assert_eq!("TETRIS", header.title);
+        assert_eq!(MbcKind::Mbc3, header.cartridge_type.mbc);
+        assert!(header.cartridge_type.has_battery);
+        assert_eq!(0x10000, header.rom_size);
+        assert_eq!(0x8000, header.ram_size);
+        assert_eq!(CgbSupport::DmgOnly, header.cgb_support);
+        assert_eq!(Licensee::Old(0x01), header.licensee);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn detects_checksum_mismatch() {
+        let mut rom = valid_rom();
+        rom[ADDRESS_ROM_VERSION as usize] = 1;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert!(!header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+    }
+
+    #[test]
+    fn truncated_rom_is_an_error() {
+        let rom = valid_rom();
+        assert_eq!(
+            CartridgeHeaderError::Truncated { len: 0x140 },
+            CartridgeHeader::parse(&rom[..0x140]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn malformed_header_is_an_error() {
+        let mut rom = valid_rom();
+        rom[ADDRESS_RAM_SIZE as usize] = 9;
+        assert_eq!(
+            CartridgeHeaderError::InvalidRamSize(9),
+            CartridgeHeader::parse(&rom).unwrap_err()
+        );
+
+        let mut rom = valid_rom();
+        rom[ADDRESS_ROM_SIZE as usize] = 3;
+        assert!(matches!(
+            CartridgeHeader::parse(&rom).unwrap_err(),
+            CartridgeHeaderError::RomSizeMismatch { .. }
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use crate::{input::KeyBindings, lcd::ColorScheme, serial::LinkKind};
+
+/// Config file used when one isn't given on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "gameboy.ini";
+
+/// User settings. Read from an INI style file of `key = value` lines grouped under `[section]`
+/// headers. Lines starting with `#` or `;` are comments, and so is anything after a `#` with
+/// whitespace on both sides so colours like `#E0F8D0` are kept. Anything missing keeps its default.
+///
+/// ```ini
+/// [keys]
+/// a = X
+/// start = Enter, Space
+///
+/// [display]
+/// colors = dmg
+///
+/// [serial]
+/// link = tcp-listen:127.0.0.1:8765
+/// ```
+#[derive(Default)]
+pub struct Config {
+    pub key_bindings: KeyBindings,
+    pub color_scheme: ColorScheme,
+    pub serial_link: LinkKind,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Self {
+        let mut config = Config::default();
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return config,
+            Err(err) => {
+                println!("Failed reading config file {}: {}", path.display(), err);
+                return config;
+            }
+        };
+
+        for entry in parse(&text) {
+            let result = entry.and_then(|entry| {
+                config
+                    .apply(&entry)
+                    .map_err(|err| format!("line {}: {}", entry.line, err))
+            });
+            if let Err(err) = result {
+                println!("Ignoring config in {}: {}", path.display(), err);
+            }
+        }
+
+        config
+    }
+
+    fn apply(&mut self, entry: &Entry) -> Result<(), String> {
+        match entry.section.as_str() {
+            "keys" => self.key_bindings.set(&entry.key, &entry.value),
+            "display" => match entry.key.as_str() {
+                "colors" => {
+                    self.color_scheme = ColorScheme::parse(&entry.value)?;
+                    Ok(())
+                }
+                _ => Err(format!("unknown display setting {}", entry.key)),
+            },
+            "serial" => match entry.key.as_str() {
+                "link" => {
+                    self.serial_link = LinkKind::parse(&entry.value)?;
+                    Ok(())
+                }
+                _ => Err(format!("unknown serial setting {}", entry.key)),
+            },
+            _ => Err(format!("unknown section [{}]", entry.section)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    section: String,
+    key: String,
+    value: String,
+    line: usize,
+}
+
+fn parse(text: &str) -> Vec<Result<Entry, String>> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_lowercase();
+        } else if let Some((key, value)) = line.split_once('=') {
+            entries.push(Ok(Entry {
+                section: section.clone(),
+                key: key.trim().to_lowercase(),
+                value: value.trim().to_string(),
+                line: line_number,
+            }));
+        } else {
+            entries.push(Err(format!("line {}: expected `key = value`", line_number)));
+        }
+    }
+
+    entries
+}
+
+fn strip_comment(line: &str) -> &str {
+    let comment_start = line.char_indices().find(|(i, c)| {
+        let next = line[i + 1..].chars().next();
+        *c == '#' && line[..*i].ends_with(char::is_whitespace) && next.is_none_or(char::is_whitespace)
+    });
+    match comment_start {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{parse, Entry};
+
+    #[test]
+    fn parse_sections_and_comments() {
+        let text = indoc! {"
+            # comment
+            [Keys]
+            A = X # trailing comment
+
+            not an entry
+        "};
+
+        let entries = parse(text);
+        assert_eq!(
+            Ok(Entry {
+                section: "keys".to_string(),
+                key: "a".to_string(),
+                value: "X".to_string(),
+                line: 3,
+            }),
+            entries[0]
+        );
+        assert!(entries[1].is_err());
+        assert_eq!(2, entries.len());
+    }
+
+    #[test]
+    fn parse_hex_colors() {
+        let text = indoc! {"
+            ; comment
+            [display]
+            colors = #E0F8D0, #88C070, #346856, #081820 # trailing comment
+        "};
+
+        let entries = parse(text);
+        assert_eq!(
+            Ok(Entry {
+                section: "display".to_string(),
+                key: "colors".to_string(),
+                value: "#E0F8D0, #88C070, #346856, #081820".to_string(),
+                line: 3,
+            }),
+            entries[0]
+        );
+        assert_eq!(1, entries.len());
+    }
+}
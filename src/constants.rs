@@ -1,17 +1,52 @@
 pub const ADDRESS_FIRST_INTERRUPT_HANDLER: u16 = 0x40;
-pub const ADDRESS_ROM_SIZE: u16 = 0x0149;
+pub const ADDRESS_TITLE: u16 = 0x0134;
+pub const ADDRESS_MANUFACTURER_CODE: u16 = 0x013F;
+pub const ADDRESS_CGB_FLAG: u16 = 0x0143;
+pub const ADDRESS_NEW_LICENSEE: u16 = 0x0144;
+pub const ADDRESS_SGB_FLAG: u16 = 0x0146;
+pub const ADDRESS_CARTRIDGE_TYPE: u16 = 0x0147;
+pub const ADDRESS_ROM_SIZE: u16 = 0x0148;
 pub const ADDRESS_RAM_SIZE: u16 = 0x0149;
+pub const ADDRESS_DESTINATION_CODE: u16 = 0x014A;
+pub const ADDRESS_OLD_LICENSEE: u16 = 0x014B;
+pub const ADDRESS_ROM_VERSION: u16 = 0x014C;
+pub const ADDRESS_HEADER_CHECKSUM: u16 = 0x014D;
+pub const ADDRESS_GLOBAL_CHECKSUM: u16 = 0x014E;
 pub const ADDRESS_TILEDATA_1: u16 = 0x8000;
 pub const ADDRESS_TILEDATA_2: u16 = 0x8800;
 pub const ADDRESS_TILEMAP_1: u16 = 0x9800;
 pub const ADDRESS_TILEMAP_2: u16 = 0x9C00;
 pub const ADDRESS_OAM_START: u16 = 0xFE00;
 pub const ADDRESS_JOYP: u16 = 0xFF00;
+pub const ADDRESS_SB: u16 = 0xFF01;
+pub const ADDRESS_SC: u16 = 0xFF02;
 pub const ADDRESS_DIV: u16 = 0xFF04;
 pub const ADDRESS_TIMA: u16 = 0xFF05;
 pub const ADDRESS_TMA: u16 = 0xFF06;
 pub const ADDRESS_TAC: u16 = 0xFF07;
 pub const ADDRESS_IF: u16 = 0xFF0F;
+pub const ADDRESS_NR10: u16 = 0xFF10;
+pub const ADDRESS_NR11: u16 = 0xFF11;
+pub const ADDRESS_NR12: u16 = 0xFF12;
+pub const ADDRESS_NR13: u16 = 0xFF13;
+pub const ADDRESS_NR14: u16 = 0xFF14;
+pub const ADDRESS_NR21: u16 = 0xFF16;
+pub const ADDRESS_NR22: u16 = 0xFF17;
+pub const ADDRESS_NR23: u16 = 0xFF18;
+pub const ADDRESS_NR24: u16 = 0xFF19;
+pub const ADDRESS_NR30: u16 = 0xFF1A;
+pub const ADDRESS_NR31: u16 = 0xFF1B;
+pub const ADDRESS_NR32: u16 = 0xFF1C;
+pub const ADDRESS_NR33: u16 = 0xFF1D;
+pub const ADDRESS_NR34: u16 = 0xFF1E;
+pub const ADDRESS_NR41: u16 = 0xFF20;
+pub const ADDRESS_NR42: u16 = 0xFF21;
+pub const ADDRESS_NR43: u16 = 0xFF22;
+pub const ADDRESS_NR44: u16 = 0xFF23;
+pub const ADDRESS_NR50: u16 = 0xFF24;
+pub const ADDRESS_NR51: u16 = 0xFF25;
+pub const ADDRESS_NR52: u16 = 0xFF26;
+pub const ADDRESS_WAVE_RAM_START: u16 = 0xFF30;
 pub const ADDRESS_LCDC: u16 = 0xFF40;
 pub const ADDRESS_STAT: u16 = 0xFF41;
 pub const ADDRESS_SCY: u16 = 0xFF42;
@@ -21,6 +56,22 @@ pub const ADDRESS_DMA_CONTROL: u16 = 0xFF46;
 pub const ADDRESS_WY: u16 = 0xFF4A;
 pub const ADDRESS_WX: u16 = 0xFF4B;
 pub const ADDRESS_LY: u16 = 0xFF44;
+pub const ADDRESS_BGP: u16 = 0xFF47;
+pub const ADDRESS_OBP0: u16 = 0xFF48;
+pub const ADDRESS_OBP1: u16 = 0xFF49;
+pub const ADDRESS_BOOT_ROM_DISABLE: u16 = 0xFF50;
+pub const ADDRESS_KEY1: u16 = 0xFF4D;
+pub const ADDRESS_VBK: u16 = 0xFF4F;
+pub const ADDRESS_HDMA1: u16 = 0xFF51;
+pub const ADDRESS_HDMA2: u16 = 0xFF52;
+pub const ADDRESS_HDMA3: u16 = 0xFF53;
+pub const ADDRESS_HDMA4: u16 = 0xFF54;
+pub const ADDRESS_HDMA5: u16 = 0xFF55;
+pub const ADDRESS_BCPS: u16 = 0xFF68;
+pub const ADDRESS_BCPD: u16 = 0xFF69;
+pub const ADDRESS_OCPS: u16 = 0xFF6A;
+pub const ADDRESS_OCPD: u16 = 0xFF6B;
+pub const ADDRESS_SVBK: u16 = 0xFF70;
 pub const ADDRESS_STACK_START: u16 = 0xFFFE;
 pub const ADDRESS_IE: u16 = 0xFFFF;
 
@@ -33,7 +84,25 @@ pub const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
 pub const LCDC_WINDOW_TILEMAP: u8 = 1 << 6;
 pub const LCDC_LCD_ENABLE: u8 = 1 << 7;
 
+pub const OBJ_ATTR_PALETTE: u8 = 1 << 4;
+pub const OBJ_ATTR_X_FLIP: u8 = 1 << 5;
+pub const OBJ_ATTR_Y_FLIP: u8 = 1 << 6;
+pub const OBJ_ATTR_PRIORITY: u8 = 1 << 7;
+pub const OBJ_ATTR_CGB_PALETTE: u8 = 0b111;
+pub const OBJ_ATTR_BANK: u8 = 1 << 3;
+
+/// CGB background map attributes, stored in VRAM bank 1 at the same address as the tile index
+pub const BG_ATTR_PALETTE: u8 = 0b111;
+pub const BG_ATTR_BANK: u8 = 1 << 3;
+pub const BG_ATTR_X_FLIP: u8 = 1 << 5;
+pub const BG_ATTR_Y_FLIP: u8 = 1 << 6;
+pub const BG_ATTR_PRIORITY: u8 = 1 << 7;
+
 pub const PPU_MODE_OAM_SCAN: u8 = 2;
 pub const PPU_MODE_RENDER_PIXEL: u8 = 3;
 pub const PPU_MODE_HORIZ_BLANK: u8 = 0;
 pub const PPU_MODE_VERT_BLANK: u8 = 1;
+
+pub const T_CYCLES_PER_M_CYCLE: u64 = 4;
+pub const T_CYCLES_PER_SECOND: u64 = 4194304;
+pub const T_CYCLES_PER_FRAME: u64 = 70224;
@@ -0,0 +1,232 @@
+use crate::{constants::*, memory::MemoryController};
+
+/// M-cycles between writing the DMA register and the first byte being copied
+pub const OAM_DMA_START_DELAY: u8 = 1;
+const OAM_DMA_LENGTH: u16 = 0xA0;
+/// M-cycles the CPU is paused for each 16 byte block VRAM DMA copies at normal speed
+const VRAM_DMA_BLOCK_CYCLES: u64 = 8;
+
+// https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+/// Runs OAM DMA for a single M-cycle, copying one byte from the source to OAM
+pub fn step_oam_dma(mem: &mut dyn MemoryController) {
+    let dma = &mut mem.shared_data_mut().oam_dma;
+    if let Some((source, delay)) = dma.requested {
+        if delay == 0 {
+            // Starting a new transfer cancels the one in progress
+            dma.source = Some(source);
+            dma.progress = 0;
+            dma.requested = None;
+        } else {
+            dma.requested = Some((source, delay - 1));
+        }
+    }
+
+    let Some(source) = dma.source else {
+        return;
+    };
+    let offset = dma.progress;
+    dma.progress += 1;
+    if dma.progress == OAM_DMA_LENGTH {
+        dma.source = None;
+    }
+
+    // Sources past work RAM read from work RAM again, like echo RAM
+    let mut address = source + offset;
+    if address >= 0xE000 {
+        address -= 0x2000;
+    }
+
+    let val = mem.read_8_sys(address);
+    mem.write_8_sys(ADDRESS_OAM_START + offset, val);
+}
+
+// https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+/// Runs CGB VRAM DMA. A general purpose transfer copies everything at once and an HBlank
+/// transfer copies one block at the start of every horizontal blank. Returns the M-cycles the
+/// CPU is paused for while the copy happens.
+pub fn step_vram_dma(mem: &mut dyn MemoryController) -> u64 {
+    let dma = &mut mem.shared_data_mut().vram_dma;
+    let hblank_started = std::mem::take(&mut dma.hblank_started);
+    let blocks = if std::mem::take(&mut dma.general_requested) {
+        dma.blocks_left
+    } else if dma.hblank_active && hblank_started {
+        1
+    } else {
+        return 0;
+    };
+
+    for _ in 0..blocks {
+        copy_vram_dma_block(mem);
+    }
+
+    let dma = &mut mem.shared_data_mut().vram_dma;
+    dma.blocks_left -= blocks;
+    let hdma5 = if dma.blocks_left == 0 {
+        dma.hblank_active = false;
+        0xFF
+    } else {
+        dma.blocks_left - 1
+    };
+    mem.write_8_sys(ADDRESS_HDMA5, hdma5);
+
+    // The copy takes the same real time in double speed, which is twice the CPU cycles
+    let block_cycles = if mem.shared_data().double_speed {
+        VRAM_DMA_BLOCK_CYCLES * 2
+    } else {
+        VRAM_DMA_BLOCK_CYCLES
+    };
+    blocks as u64 * block_cycles
+}
+
+fn copy_vram_dma_block(mem: &mut dyn MemoryController) {
+    let dma = &mem.shared_data().vram_dma;
+    let (source, destination) = (dma.source, dma.destination);
+    for i in 0..0x10 {
+        let val = mem.read_8_sys(source.wrapping_add(i));
+        // The destination wraps around within VRAM
+        mem.write_8_sys(0x8000 | (destination.wrapping_add(i) & 0x1FFF), val);
+    }
+
+    let dma = &mut mem.shared_data_mut().vram_dma;
+    dma.source = source.wrapping_add(0x10);
+    dma.destination = 0x8000 | (destination.wrapping_add(0x10) & 0x1FFF);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::*, debug::metrics::DebugMetrics, memory::MemoryController, memory_controllers::basic_memory::BasicMemory, opcodes::process_instruction};
+
+    use super::{step_oam_dma, step_vram_dma};
+
+    fn step_n(mem: &mut dyn MemoryController, cycles: u32) {
+        for _ in 0..cycles {
+            step_oam_dma(mem);
+        }
+    }
+
+    fn fill(mem: &mut dyn MemoryController, start: u16, val: u8) {
+        for i in 0..0xA0 {
+            mem.write_8(start + i, val);
+        }
+    }
+
+    #[test]
+    fn copies_source_to_oam() {
+        let mut m = BasicMemory::default();
+        for i in 0..0xA0 {
+            m.write_8(0xC100 + i, i as u8);
+        }
+
+        m.write_8(ADDRESS_DMA_CONTROL, 0xC1);
+        step_n(&mut m, 1);
+        assert!(!m.shared_data().oam_dma.active(), "the transfer starts after a delay");
+
+        step_n(&mut m, 0xA0);
+        assert!(!m.shared_data().oam_dma.active());
+        for i in 0..0xA0 {
+            assert_eq!(i as u8, m.read_8(ADDRESS_OAM_START + i));
+        }
+    }
+
+    #[test]
+    fn cpu_only_reaches_hram_during_transfer() {
+        let mut m = BasicMemory::default();
+        m.write_8(0xC000, 0x12);
+        m.write_8(0xFF80, 0x34);
+
+        m.write_8(ADDRESS_DMA_CONTROL, 0xC0);
+        step_n(&mut m, 2);
+        assert_eq!(0xFF, m.read_8(0xC000));
+        assert_eq!(0xFF, m.read_8(ADDRESS_OAM_START));
+        assert_eq!(0x34, m.read_8(0xFF80));
+
+        m.write_8(0xC000, 0x56);
+        m.write_8(0xFF80, 0x78);
+        assert_eq!(0x12, m.read_8_sys(0xC000));
+        assert_eq!(0x78, m.read_8(0xFF80));
+    }
+
+    #[test]
+    fn hl_writes_blocked_during_transfer() {
+        let mut m = BasicMemory::default();
+        // LD (HL),A running from HRAM, the only place code can run during a transfer
+        m.write_8(0xFF80, 0b01_110_111);
+        m.r().pc = 0xFF80;
+        m.r().a = 0x99;
+        m.r().hl.s16(0xC010);
+
+        m.write_8(ADDRESS_DMA_CONTROL, 0xC1);
+        step_n(&mut m, 2);
+        process_instruction(&mut m, &mut DebugMetrics::new());
+        assert_eq!(0, m.read_8_sys(0xC010));
+    }
+
+    #[test]
+    fn restart_during_transfer() {
+        let mut m = BasicMemory::default();
+        fill(&mut m, 0xC000, 0x11);
+        fill(&mut m, 0xD000, 0x22);
+
+        m.write_8(ADDRESS_DMA_CONTROL, 0xC0);
+        step_n(&mut m, 51);
+        m.write_8(ADDRESS_DMA_CONTROL, 0xD0);
+        // The old transfer copies one more byte before the new one starts
+        step_n(&mut m, 1);
+        assert_eq!(0x11, m.read_8_sys(ADDRESS_OAM_START + 50));
+        assert_eq!(0x00, m.read_8_sys(ADDRESS_OAM_START + 51));
+
+        step_n(&mut m, 0xA0);
+        for i in 0..0xA0 {
+            assert_eq!(0x22, m.read_8(ADDRESS_OAM_START + i));
+        }
+    }
+
+    fn start_vram_dma(mem: &mut dyn MemoryController, source: u16, destination: u16, hdma5: u8) {
+        mem.write_8(ADDRESS_HDMA1, (source >> 8) as u8);
+        mem.write_8(ADDRESS_HDMA2, source as u8);
+        mem.write_8(ADDRESS_HDMA3, (destination >> 8) as u8);
+        mem.write_8(ADDRESS_HDMA4, destination as u8);
+        mem.write_8(ADDRESS_HDMA5, hdma5);
+    }
+
+    #[test]
+    fn general_vram_dma_copies_everything() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().cgb_mode = true;
+        for i in 0..0x20 {
+            m.write_8(0xC000 + i, i as u8);
+        }
+
+        start_vram_dma(&mut m, 0xC000, 0x8100, 0x01);
+        assert_eq!(16, step_vram_dma(&mut m));
+        for i in 0..0x20 {
+            assert_eq!(i as u8, m.read_8(0x8100 + i));
+        }
+        assert_eq!(0xFF, m.read_8(ADDRESS_HDMA5));
+        assert_eq!(0, step_vram_dma(&mut m));
+    }
+
+    #[test]
+    fn hblank_vram_dma_copies_a_block_per_hblank() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().cgb_mode = true;
+        m.write_8_sys(ADDRESS_LCDC, 0x80);
+        fill(&mut m, 0xC000, 0x11);
+
+        start_vram_dma(&mut m, 0xC000, 0x8000, 0x82);
+        assert_eq!(0, step_vram_dma(&mut m));
+        assert_eq!(0x02, m.read_8(ADDRESS_HDMA5));
+
+        m.shared_data_mut().vram_dma.hblank_started = true;
+        assert_eq!(8, step_vram_dma(&mut m));
+        assert_eq!(0x11, m.read_8(0x800F));
+        assert_eq!(0x00, m.read_8(0x8010));
+        assert_eq!(0x01, m.read_8(ADDRESS_HDMA5));
+
+        // Stopping the transfer leaves the length that was left readable
+        m.write_8(ADDRESS_HDMA5, 0x00);
+        assert_eq!(0x81, m.read_8(ADDRESS_HDMA5));
+        m.shared_data_mut().vram_dma.hblank_started = true;
+        assert_eq!(0, step_vram_dma(&mut m));
+    }
+}
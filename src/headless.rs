@@ -0,0 +1,246 @@
+use crate::{
+    cartridge_header::CartridgeHeaderError, config::Config, lcd::Lcd, memory::MemoryController, serial::{NoPartner, Serial}, system::Emulator
+};
+
+/// `LD B,B`, which Mooneye test ROMs run as a breakpoint once they've finished
+const MOONEYE_BREAKPOINT: u8 = 0x40;
+/// B, C, D, E, H and L after a Mooneye test passes. A failed test fills them with 0x42.
+const MOONEYE_PASS_REGISTERS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// Blargg's newer ROMs write their status to 0xA000 and mark it valid with this at 0xA001
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// Status while a Blargg test is still running. 0 means it passed and anything else failed.
+const BLARGG_RUNNING: u8 = 0x80;
+
+/// When to stop running without a window and how to tell if the ROM passed
+#[derive(Debug, Default)]
+pub struct HeadlessOptions {
+    /// Stop after this many frames
+    pub max_frames: Option<u64>,
+    /// Stop after this many machine cycles
+    pub max_cycles: Option<u64>,
+    /// Passed once the serial output contains this, Blargg's test ROMs print "Passed"
+    pub pass_serial: Option<String>,
+    /// Failed once the serial output contains this
+    pub fail_serial: Option<String>,
+    /// Check the status Blargg's ROMs write to cartridge RAM, for the ones that don't use serial
+    pub blargg_memory: bool,
+    /// Stop at Mooneye's `LD B,B` breakpoint and check the registers
+    pub mooneye: bool,
+}
+
+impl HeadlessOptions {
+    fn has_condition(&self) -> bool {
+        self.pass_serial.is_some() || self.fail_serial.is_some() || self.blargg_memory || self.mooneye
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessResult {
+    Passed,
+    Failed,
+    /// A frame or cycle limit was reached before the ROM passed or failed
+    TimedOut,
+    /// A frame or cycle limit was reached and there was nothing to wait for
+    Finished,
+}
+
+impl HeadlessResult {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HeadlessResult::Passed | HeadlessResult::Finished => 0,
+            HeadlessResult::Failed => 1,
+            HeadlessResult::TimedOut => 2,
+        }
+    }
+}
+
+/// Runs a ROM without a window, printing what it sent over the serial port when it stops
+pub fn boot_headless(rom: Vec<u8>, boot_rom: Option<Vec<u8>>, config: Config, options: &HeadlessOptions) -> Result<HeadlessResult, CartridgeHeaderError> {
+    let link = config.serial_link.open().unwrap_or_else(|err| {
+        println!("Failed to connect the link cable, continuing without it: {}", err);
+        Box::new(NoPartner)
+    });
+    let mut emulator = Emulator::new(rom, boot_rom, Lcd::new(config.color_scheme), Serial::new(link))?;
+    println!("{}", emulator.header());
+
+    let result = run_headless(&mut emulator, options);
+    if !emulator.serial.output().is_empty() {
+        println!("Serial output:\n{}", String::from_utf8_lossy(emulator.serial.output()));
+    }
+    println!("Result: {:?}", result);
+    Ok(result)
+}
+
+/// Runs as fast as possible until the ROM passes or fails, or a limit is reached.
+/// Without any limits this only returns once the ROM passes or fails.
+pub fn run_headless(emulator: &mut Emulator, options: &HeadlessOptions) -> HeadlessResult {
+    let mut frames = 0;
+    let mut cycles = 0;
+    let mut serial_checked = 0;
+
+    loop {
+        if options.mooneye {
+            if let Some(passed) = mooneye_result(emulator.mem()) {
+                return if passed { HeadlessResult::Passed } else { HeadlessResult::Failed };
+            }
+        }
+
+        let step = emulator.step();
+        cycles += step.cycles;
+
+        let output = emulator.serial.output();
+        if output.len() != serial_checked {
+            serial_checked = output.len();
+            if let Some(result) = serial_result(output, options) {
+                return result;
+            }
+        }
+
+        if step.frame_ready {
+            frames += 1;
+            // Nothing plays the samples so they'd pile up
+            emulator.apu.take_samples();
+
+            if options.blargg_memory {
+                if let Some(result) = blargg_memory_result(emulator.mem()) {
+                    return result;
+                }
+            }
+        }
+
+        let frames_done = options.max_frames.is_some_and(|max| frames >= max);
+        let cycles_done = options.max_cycles.is_some_and(|max| cycles >= max);
+        if frames_done || cycles_done {
+            return if options.has_condition() { HeadlessResult::TimedOut } else { HeadlessResult::Finished };
+        }
+    }
+}
+
+/// Whether the serial output so far says the ROM passed or failed
+fn serial_result(output: &[u8], options: &HeadlessOptions) -> Option<HeadlessResult> {
+    let text = String::from_utf8_lossy(output);
+    if options.fail_serial.as_ref().is_some_and(|fail| text.contains(fail.as_str())) {
+        Some(HeadlessResult::Failed)
+    } else if options.pass_serial.as_ref().is_some_and(|pass| text.contains(pass.as_str())) {
+        Some(HeadlessResult::Passed)
+    } else {
+        None
+    }
+}
+
+/// Whether the status a Blargg test wrote to cartridge RAM says it passed or failed
+fn blargg_memory_result(mem: &dyn MemoryController) -> Option<HeadlessResult> {
+    let signature = [0xA001, 0xA002, 0xA003].map(|addr| mem.read_8_sys(addr));
+    match mem.read_8_sys(0xA000) {
+        _ if signature != BLARGG_SIGNATURE => None,
+        BLARGG_RUNNING => None,
+        0 => Some(HeadlessResult::Passed),
+        _ => Some(HeadlessResult::Failed),
+    }
+}
+
+/// Whether a Mooneye test passed, or `None` if it hasn't reached its breakpoint yet
+fn mooneye_result(mem: &mut dyn MemoryController) -> Option<bool> {
+    if mem.read_8_sys(mem.r_i().pc) != MOONEYE_BREAKPOINT {
+        return None;
+    }
+    let r = mem.r_i();
+    Some([r.bc.ind.0, r.bc.ind.1, r.de.ind.0, r.de.ind.1, r.hl.ind.0, r.hl.ind.1] == MOONEYE_PASS_REGISTERS)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{constants::ADDRESS_CARTRIDGE_TYPE, lcd::Lcd, serial::{NoPartner, Serial}, system::Emulator};
+
+    use super::{run_headless, HeadlessOptions, HeadlessResult};
+
+    /// A ROM with no cartridge hardware that runs `code` after the header
+    fn emulator_running(code: &[u8]) -> Emulator {
+        emulator_with_cartridge_running(0x00, code)
+    }
+
+    fn emulator_with_cartridge_running(cartridge_type: u8, code: &[u8]) -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        // JP 0x150
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[ADDRESS_CARTRIDGE_TYPE as usize] = cartridge_type;
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        Emulator::new(rom, None, Lcd::new(Default::default()), Serial::new(Box::new(NoPartner))).unwrap()
+    }
+
+    /// Loads each register with its value then hits the `LD B,B` breakpoint
+    fn mooneye_code(registers: [u8; 6]) -> Vec<u8> {
+        let load_opcodes = [0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E];
+        let mut code: Vec<u8> = load_opcodes.iter().zip(registers).flat_map(|(op, val)| [*op, val]).collect();
+        code.push(0x40);
+        code
+    }
+
+    /// Sends `text` over the serial port like Blargg's ROMs do, then loops forever
+    fn serial_print_code(text: &str) -> Vec<u8> {
+        let mut code = vec![];
+        for c in text.bytes() {
+            // LD A,c; LDH (SB),A; LD A,0x81; LDH (SC),A
+            code.extend([0x3E, c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+            // LDH A,(SC); BIT 7,A; JR NZ,-6
+            code.extend([0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA]);
+        }
+        // JR -2
+        code.extend([0x18, 0xFE]);
+        code
+    }
+
+    #[rstest]
+    #[case([3, 5, 8, 13, 21, 34], HeadlessResult::Passed)]
+    #[case([0x42; 6], HeadlessResult::Failed)]
+    fn mooneye_signature(#[case] registers: [u8; 6], #[case] expected: HeadlessResult) {
+        let mut emulator = emulator_running(&mooneye_code(registers));
+        let options = HeadlessOptions { mooneye: true, max_frames: Some(10), ..Default::default() };
+        assert_eq!(expected, run_headless(&mut emulator, &options));
+    }
+
+    #[rstest]
+    #[case("Passed", HeadlessResult::Passed)]
+    #[case("Failed", HeadlessResult::Failed)]
+    #[case("Running", HeadlessResult::TimedOut)]
+    fn serial_output(#[case] text: &str, #[case] expected: HeadlessResult) {
+        let mut emulator = emulator_running(&serial_print_code(text));
+        let options = HeadlessOptions {
+            pass_serial: Some("Passed".into()),
+            fail_serial: Some("Failed".into()),
+            max_frames: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(expected, run_headless(&mut emulator, &options));
+        assert_eq!(text.as_bytes(), emulator.serial.output());
+    }
+
+    #[rstest]
+    #[case(0x00, HeadlessResult::Passed)]
+    #[case(0x01, HeadlessResult::Failed)]
+    #[case(0x80, HeadlessResult::TimedOut)]
+    fn blargg_memory_status(#[case] status: u8, #[case] expected: HeadlessResult) {
+        let mut code = vec![];
+        for (addr, val) in [(0xA001u16, 0xDE), (0xA002, 0xB0), (0xA003, 0x61), (0xA000, status)] {
+            // LD A,val; LD (addr),A
+            code.extend([0x3E, val, 0xEA, addr as u8, (addr >> 8) as u8]);
+        }
+        // JR -2
+        code.extend([0x18, 0xFE]);
+        // ROM+RAM
+        let mut emulator = emulator_with_cartridge_running(0x08, &code);
+
+        let options = HeadlessOptions { blargg_memory: true, max_frames: Some(10), ..Default::default() };
+        assert_eq!(expected, run_headless(&mut emulator, &options));
+    }
+
+    #[test]
+    fn stops_at_cycle_limit() {
+        let mut emulator = emulator_running(&[0x18, 0xFE]);
+        let options = HeadlessOptions { max_cycles: Some(1000), ..Default::default() };
+        assert_eq!(HeadlessResult::Finished, run_headless(&mut emulator, &options));
+        assert_eq!(0x150, emulator.mem().r_i().pc);
+    }
+}
@@ -0,0 +1,173 @@
+use macroquad::input::{is_key_down, KeyCode};
+
+use crate::memory::{Inputs, MemoryController};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+    Reset,
+}
+
+/// Names used for each button in the config file
+const BUTTON_NAMES: [(&str, Button); 9] = [
+    ("up", Button::Up),
+    ("down", Button::Down),
+    ("left", Button::Left),
+    ("right", Button::Right),
+    ("a", Button::A),
+    ("b", Button::B),
+    ("start", Button::Start),
+    ("select", Button::Select),
+    ("reset", Button::Reset),
+];
+
+/// Names used for keys in the config file
+const KEY_NAMES: [(&str, KeyCode); 63] = [
+    ("A", KeyCode::A), ("B", KeyCode::B), ("C", KeyCode::C), ("D", KeyCode::D),
+    ("E", KeyCode::E), ("F", KeyCode::F), ("G", KeyCode::G), ("H", KeyCode::H),
+    ("I", KeyCode::I), ("J", KeyCode::J), ("K", KeyCode::K), ("L", KeyCode::L),
+    ("M", KeyCode::M), ("N", KeyCode::N), ("O", KeyCode::O), ("P", KeyCode::P),
+    ("Q", KeyCode::Q), ("R", KeyCode::R), ("S", KeyCode::S), ("T", KeyCode::T),
+    ("U", KeyCode::U), ("V", KeyCode::V), ("W", KeyCode::W), ("X", KeyCode::X),
+    ("Y", KeyCode::Y), ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2), ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4), ("5", KeyCode::Key5), ("6", KeyCode::Key6), ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+    ("Up", KeyCode::Up), ("Down", KeyCode::Down), ("Left", KeyCode::Left), ("Right", KeyCode::Right),
+    ("Enter", KeyCode::Enter), ("Space", KeyCode::Space), ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab), ("Escape", KeyCode::Escape),
+    ("LeftShift", KeyCode::LeftShift), ("RightShift", KeyCode::RightShift),
+    ("LeftControl", KeyCode::LeftControl), ("RightControl", KeyCode::RightControl),
+    ("LeftAlt", KeyCode::LeftAlt), ("RightAlt", KeyCode::RightAlt),
+    ("F1", KeyCode::F1), ("F2", KeyCode::F2), ("F3", KeyCode::F3), ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5), ("F6", KeyCode::F6), ("F7", KeyCode::F7), ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9), ("F10", KeyCode::F10), ("F11", KeyCode::F11), ("F12", KeyCode::F12),
+];
+
+fn button_index(button: Button) -> usize {
+    BUTTON_NAMES.iter().position(|(_, b)| *b == button).unwrap()
+}
+
+/// Which keyboard keys press each button. A button can have any number of keys.
+pub struct KeyBindings {
+    keys: [Vec<KeyCode>; BUTTON_NAMES.len()],
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = KeyBindings {
+            keys: Default::default(),
+        };
+        bindings.keys[button_index(Button::Up)] = vec![KeyCode::Up];
+        bindings.keys[button_index(Button::Down)] = vec![KeyCode::Down];
+        bindings.keys[button_index(Button::Left)] = vec![KeyCode::Left];
+        bindings.keys[button_index(Button::Right)] = vec![KeyCode::Right];
+        bindings.keys[button_index(Button::A)] = vec![KeyCode::X];
+        bindings.keys[button_index(Button::B)] = vec![KeyCode::Z];
+        bindings.keys[button_index(Button::Start)] = vec![KeyCode::Enter];
+        bindings.keys[button_index(Button::Select)] = vec![KeyCode::Backspace];
+        bindings.keys[button_index(Button::Reset)] = vec![KeyCode::F5];
+        bindings
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, button: Button) -> &[KeyCode] {
+        &self.keys[button_index(button)]
+    }
+
+    /// Replaces the keys for a button from config file names, e.g. `start`, `Enter, Space`
+    pub fn set(&mut self, button_name: &str, key_names: &str) -> Result<(), String> {
+        let button = BUTTON_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(button_name))
+            .map(|(_, b)| *b)
+            .ok_or_else(|| format!("Unknown button {}", button_name))?;
+
+        let keys = key_names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                KEY_NAMES
+                    .iter()
+                    .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+                    .map(|(_, key)| *key)
+                    .ok_or_else(|| format!("Unknown key {}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.keys[button_index(button)] = keys;
+        Ok(())
+    }
+}
+
+/// Reads the keyboard into the joypad once per frame
+pub struct Input {
+    bindings: KeyBindings,
+    reset_held: bool,
+}
+
+impl Input {
+    pub fn new(bindings: KeyBindings) -> Self {
+        Input {
+            bindings,
+            reset_held: false,
+        }
+    }
+
+    /// Updates the joypad from the current keyboard state. Returns true when reset was just
+    /// pressed.
+    pub fn poll(&mut self, mem: &mut dyn MemoryController) -> bool {
+        let pressed = |button| self.bindings.keys(button).iter().any(|key| is_key_down(*key));
+
+        let inputs = Inputs {
+            down: pressed(Button::Down),
+            up: pressed(Button::Up),
+            left: pressed(Button::Left),
+            right: pressed(Button::Right),
+            start: pressed(Button::Start),
+            select: pressed(Button::Select),
+            b: pressed(Button::B),
+            a: pressed(Button::A),
+            reset: pressed(Button::Reset),
+        };
+
+        let reset_pressed = inputs.reset && !self.reset_held;
+        self.reset_held = inputs.reset;
+        mem.shared_data_mut().inputs = inputs;
+        reset_pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::input::KeyCode;
+
+    use super::{Button, KeyBindings};
+
+    #[test]
+    fn set_binding_from_names() {
+        let mut bindings = KeyBindings::default();
+        bindings.set("Start", "enter, space").unwrap();
+        assert_eq!(&[KeyCode::Enter, KeyCode::Space], bindings.keys(Button::Start));
+
+        bindings.set("reset", "").unwrap();
+        assert!(bindings.keys(Button::Reset).is_empty());
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        let mut bindings = KeyBindings::default();
+        assert!(bindings.set("turbo", "A").is_err());
+        assert!(bindings.set("a", "NotAKey").is_err());
+        assert_eq!(&[KeyCode::X], bindings.keys(Button::A));
+    }
+}
@@ -5,44 +5,127 @@ use macroquad::prelude::*;
 pub const SCREEN_WIDTH: u16 = 160;
 pub const SCREEN_HEIGHT: u16 = 144;
 
+/// The four shades the LCD can show, lightest first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorScheme {
+    pub shades: [Color; 4],
+}
+
+impl ColorScheme {
+    /// Plain greys, the default
+    pub const GREY: ColorScheme = ColorScheme {
+        shades: [WHITE, LIGHTGRAY, GRAY, BLACK],
+    };
+    /// Green tint of the original Game Boy screen
+    pub const DMG: ColorScheme = ColorScheme {
+        shades: [rgb(0x9B, 0xBC, 0x0F), rgb(0x8B, 0xAC, 0x0F), rgb(0x30, 0x62, 0x30), rgb(0x0F, 0x38, 0x0F)],
+    };
+    /// Game Boy Pocket screen
+    pub const POCKET: ColorScheme = ColorScheme {
+        shades: [rgb(0xC4, 0xCF, 0xA1), rgb(0x8B, 0x95, 0x6D), rgb(0x4D, 0x53, 0x3C), rgb(0x1F, 0x1F, 0x1F)],
+    };
+
+    /// Parses a preset name (`grey`, `dmg`, `pocket`) or four comma separated RGB hex colours,
+    /// lightest first, like `#E0F8D0, #88C070, #346856, #081820`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "grey" | "gray" => return Ok(Self::GREY),
+            "dmg" => return Ok(Self::DMG),
+            "pocket" => return Ok(Self::POCKET),
+            _ => {}
+        }
+
+        let colors = value
+            .split(',')
+            .map(|c| {
+                let hex = c.trim().trim_start_matches('#');
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb_value) if hex.len() == 6 => Ok(rgb((rgb_value >> 16) as u8, (rgb_value >> 8) as u8, rgb_value as u8)),
+                    _ => Err(format!("Invalid colour {}", c.trim())),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shades = colors.try_into().map_err(|_| {
+            format!("Expected a colour scheme name or 4 colours but got {}", value)
+        })?;
+        Ok(ColorScheme { shades })
+    }
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self::GREY
+    }
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color::new(r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.)
+}
+
+/// The screen the PPU draws into. It's only memory, so it works without a window and
+/// `Display` shows it when there is one.
 pub struct Lcd {
+    color_scheme: ColorScheme,
     image: Image,
-    texture: Texture2D,
-    frame_times: VecDeque<Instant>,
-    fps_ready: bool,
 }
 
-// ref: https://github.com/not-fl3/macroquad/blob/master/examples/life.rs
 impl Lcd {
-    pub fn new() -> Self {
-        let mut lcd = Lcd {
+    pub fn new(color_scheme: ColorScheme) -> Self {
+        Lcd {
+            color_scheme,
             image: Image::gen_image_color(SCREEN_WIDTH, SCREEN_HEIGHT, WHITE),
-            texture: Texture2D::empty(),
-            frame_times: VecDeque::new(),
-            fps_ready: false,
-        };
-        lcd.texture = Texture2D::from_image(&lcd.image);
-        lcd
+        }
     }
 
-    pub fn start_new_frame(&mut self) {
-        clear_background(WHITE);
+    pub fn image(&self) -> &Image {
+        &self.image
     }
 
-    pub fn draw_pixel(&mut self, x: u8, y: u8, color: u8) {
-        let show_color = match color {
-            0 => WHITE,
-            1 => LIGHTGRAY,
-            2 => GRAY,
-            3 => BLACK,
-            _ => PINK,
-        };
+    /// Fills the screen with the lightest shade, which is what the LCD shows while it's off
+    pub fn clear(&mut self) {
+        self.image = Image::gen_image_color(SCREEN_WIDTH, SCREEN_HEIGHT, self.color_scheme.shades[0]);
+    }
+
+    /// Draws a shade from 0 (lightest) to 3 (darkest) after the palette has been applied
+    pub fn draw_pixel(&mut self, x: u8, y: u8, shade: u8) {
+        let show_color = self.color_scheme.shades.get(shade as usize).copied().unwrap_or(PINK);
 
         self.image.set_pixel(x.into(), y.into(), show_color);
     }
 
-    pub async fn show_frame(&mut self) {
-        self.texture.update(&self.image);
+    /// Draws a CGB colour, 5 bits each of red, green and blue from the lowest bits up
+    pub fn draw_pixel_rgb555(&mut self, x: u8, y: u8, color: u16) {
+        let channel = |shift: u16| {
+            let value = ((color >> shift) & 0x1F) as u8;
+            // Repeat the top bits so 0x1F maps to full brightness
+            (value << 3) | (value >> 2)
+        };
+        self.image.set_pixel(x.into(), y.into(), rgb(channel(0), channel(5), channel(10)));
+    }
+
+}
+
+/// Shows frames from the LCD in the window
+pub struct Display {
+    texture: Texture2D,
+    frame_times: VecDeque<Instant>,
+    fps_ready: bool,
+}
+
+// ref: https://github.com/not-fl3/macroquad/blob/master/examples/life.rs
+impl Display {
+    pub fn new(lcd: &Lcd) -> Self {
+        Display {
+            texture: Texture2D::from_image(lcd.image()),
+            frame_times: VecDeque::new(),
+            fps_ready: false,
+        }
+    }
+
+    pub async fn show_frame(&mut self, lcd: &Lcd) {
+        clear_background(lcd.color_scheme.shades[0]);
+        self.texture.update(lcd.image());
         draw_texture(&self.texture, 0., 0., WHITE);
 
         if crate::debug::flags::DEBUG_PRINT_FRAME_TIME {
@@ -76,3 +159,30 @@ impl Lcd {
         next_frame().await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{rgb, ColorScheme};
+
+    #[test]
+    fn parse_preset_names() {
+        assert_eq!(Ok(ColorScheme::DMG), ColorScheme::parse("DMG"));
+        assert_eq!(Ok(ColorScheme::POCKET), ColorScheme::parse(" pocket "));
+        assert_eq!(Ok(ColorScheme::GREY), ColorScheme::parse("gray"));
+    }
+
+    #[test]
+    fn parse_custom_colors() {
+        let scheme = ColorScheme::parse("#E0F8D0, #88C070, 346856, #081820").unwrap();
+        assert_eq!(rgb(0xE0, 0xF8, 0xD0), scheme.shades[0]);
+        assert_eq!(rgb(0x34, 0x68, 0x56), scheme.shades[2]);
+        assert_eq!(rgb(0x08, 0x18, 0x20), scheme.shades[3]);
+    }
+
+    #[test]
+    fn parse_invalid_colors() {
+        assert!(ColorScheme::parse("#E0F8D0, #88C070, #346856").is_err());
+        assert!(ColorScheme::parse("#E0F8D0, #88C070, #346856, #08182").is_err());
+        assert!(ColorScheme::parse("sepia").is_err());
+    }
+}
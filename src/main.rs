@@ -1,8 +1,16 @@
 extern crate bitflags;
 extern crate bitmatch;
 
+mod apu;
+mod audio;
+mod boot_rom;
+mod cartridge_header;
+mod config;
 mod constants;
 mod debug;
+mod dma;
+mod headless;
+mod input;
 mod lcd;
 mod my_lib;
 mod memory;
@@ -10,50 +18,147 @@ mod memory_controllers;
 mod model;
 mod opcodes;
 mod operations;
+mod ppu;
+mod save;
+mod serial;
 mod system;
+#[cfg(test)]
+mod test_roms;
+mod timer;
 
-use std::{env, fs};
+use std::{env, fs, path::{Path, PathBuf}};
+
+use boot_rom::BOOT_ROM_SIZE;
+use config::{Config, DEFAULT_CONFIG_PATH};
+use headless::{boot_headless, HeadlessOptions};
+use lcd::ColorScheme;
+use serial::LinkKind;
 
 use system::boot;
 
-#[macroquad::main("gameboy")]
-async fn main() {
+fn main() {
     /*
      * https://archive.org/details/GameBoyProgManVer1.1/page/n7/mode/2up?view=theater
      * general todo:
      * ✓ system registers pg 17, initial values pg 23, pg 268
      * ✓ interrupts see page 24
-     * divider timer p25
-     * main timer p25
+     * ✓ divider timer p25
+     * ✓ main timer p25
      * finish and test instructions
      * MBCs pg 215
-     * display pg 48
-     * color display for gbc?
-     * sound pg 79
-     * input (including reset switch)
+     * ✓ display pg 48
+     * ✓ color display for gbc?
+     * ✓ sound pg 79
+     * ✓ input (including reset switch)
      * ✓ cycle clock .954us or on gbc .477us switchable
      * ✓ read ROM
-     * serial communication?
-     * system startup pg 23, 127
-     * persistent saves
+     * ✓ serial communication?
+     * ✓ system startup pg 23, 127
+     * ✓ persistent saves
      *
      * Maybe todo
-     * use a manual clock instead of directly using Instants in system loop to keep
+     * ✓ use a manual clock instead of directly using Instants in system loop to keep
      *   CPU and PPU in sync instead of being non-deterministic?
      * Separate UI thread
+     * ✓ headless mode for running test ROMs
      */
 
     let args: Vec<String> = env::args().collect();
-    let rom: Vec<u8>;
     dbg!(&args);
-    if args.len() > 1 {
-        match fs::read(&args[1]) {
-            Ok(data) => rom = data,
-            Err(err) => panic!("Failed reading rom file: {}", err),
-        };
-    } else {
-        panic!("You must specify a rom path in the first argument")
+
+    let mut rom_path = None;
+    let mut config_path = PathBuf::from(DEFAULT_CONFIG_PATH);
+    let mut color_scheme = None;
+    let mut boot_rom_path = None;
+    let mut serial_link = None;
+    let mut headless = false;
+    let mut headless_options = HeadlessOptions::default();
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--config" => match args_iter.next() {
+                Some(path) => config_path = path.into(),
+                None => panic!("--config must be followed by a path"),
+            },
+            "--colors" => match args_iter.next().map(|value| ColorScheme::parse(value)) {
+                Some(Ok(scheme)) => color_scheme = Some(scheme),
+                Some(Err(err)) => panic!("Invalid --colors: {}", err),
+                None => panic!("--colors must be followed by a scheme name (grey, dmg, pocket) or 4 hex colours"),
+            },
+            "--serial" => match args_iter.next().map(|value| LinkKind::parse(value)) {
+                Some(Ok(link)) => serial_link = Some(link),
+                Some(Err(err)) => panic!("Invalid --serial: {}", err),
+                None => panic!("--serial must be followed by none, stdout, tcp:<address>, tcp-listen:<address>, unix:<path> or unix-listen:<path>"),
+            },
+            "--boot-rom" => match args_iter.next() {
+                Some(path) => boot_rom_path = Some(path),
+                None => panic!("--boot-rom must be followed by a path"),
+            },
+            // Run without a window and exit with 0 if the ROM passed, 1 if it failed or 2 if it timed out
+            "--headless" => headless = true,
+            "--frames" => match args_iter.next().map(|value| value.parse()) {
+                Some(Ok(frames)) => headless_options.max_frames = Some(frames),
+                _ => panic!("--frames must be followed by a number of frames"),
+            },
+            "--cycles" => match args_iter.next().map(|value| value.parse()) {
+                Some(Ok(cycles)) => headless_options.max_cycles = Some(cycles),
+                _ => panic!("--cycles must be followed by a number of machine cycles"),
+            },
+            "--pass-serial" => match args_iter.next() {
+                Some(text) => headless_options.pass_serial = Some(text.clone()),
+                None => panic!("--pass-serial must be followed by the text that means the ROM passed"),
+            },
+            "--fail-serial" => match args_iter.next() {
+                Some(text) => headless_options.fail_serial = Some(text.clone()),
+                None => panic!("--fail-serial must be followed by the text that means the ROM failed"),
+            },
+            // Blargg's test ROMs print their result over the serial port or write it to cartridge RAM
+            "--blargg" => {
+                headless_options.pass_serial = Some("Passed".into());
+                headless_options.fail_serial = Some("Failed".into());
+                headless_options.blargg_memory = true;
+            }
+            "--mooneye" => headless_options.mooneye = true,
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => panic!("You must specify a rom path in the first argument"),
+    };
+
+    let rom: Vec<u8> = match fs::read(rom_path) {
+        Ok(data) => data,
+        Err(err) => panic!("Failed reading rom file: {}", err),
+    };
+
+    let boot_rom = boot_rom_path.map(|path| match fs::read(path) {
+        Ok(data) if data.len() == BOOT_ROM_SIZE => data,
+        Ok(data) => panic!("Boot ROM must be {} bytes but {} is {} bytes", BOOT_ROM_SIZE, path, data.len()),
+        Err(err) => panic!("Failed reading boot rom file: {}", err),
+    });
+
+    // .sav next to the ROM, the same place other emulators look
+    let save_path = Path::new(rom_path).with_extension("sav");
+    let mut config = Config::load(&config_path);
+    if let Some(color_scheme) = color_scheme {
+        config.color_scheme = color_scheme;
+    }
+    if let Some(serial_link) = serial_link {
+        config.serial_link = serial_link;
+    }
+
+    if headless {
+        match boot_headless(rom, boot_rom, config, &headless_options) {
+            Ok(result) => std::process::exit(result.exit_code()),
+            Err(err) => panic!("Failed to boot rom: {}", err),
+        }
     }
 
-    boot(rom).await;
+    macroquad::Window::new("gameboy", async move {
+        if let Err(err) = boot(rom, boot_rom, save_path, config).await {
+            panic!("Failed to boot rom: {}", err);
+        }
+    });
 }
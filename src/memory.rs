@@ -2,7 +2,7 @@ use std::fmt::{format, Debug};
 
 use bitflags::bitflags;
 
-use crate::constants::*;
+use crate::{apu, constants::*, dma::OAM_DMA_START_DELAY, memory_controllers::internal_memory::InternalMemory};
 
 bitflags! {
     #[repr(C)]
@@ -111,22 +111,139 @@ pub struct Inputs {
     pub reset: bool,
 }
 
+/// OAM DMA transfer started by writing to 0xFF46
+#[derive(Default)]
+pub struct OamDma {
+    /// Source address of the transfer in progress
+    pub source: Option<u16>,
+    /// Bytes copied so far by the transfer in progress
+    pub progress: u16,
+    /// Source of a newly requested transfer and the M-cycles before it starts. A transfer
+    /// already in progress keeps going until then.
+    pub requested: Option<(u16, u8)>,
+}
+
+impl OamDma {
+    pub fn active(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+// https://gbdev.io/pandocs/Accessing_VRAM_and_OAM.html
+/// Whether `addr` is a register that only exists on the CGB. The DMG ignores writes to them
+/// and reads them as 0xFF.
+pub fn is_cgb_register(addr: u16) -> bool {
+    matches!(addr, ADDRESS_KEY1 | ADDRESS_VBK | ADDRESS_HDMA1..=ADDRESS_HDMA5 | ADDRESS_SVBK | ADDRESS_BCPS..=ADDRESS_OCPD)
+}
+
+/// Whether the PPU is using the memory at `addr` so the CPU can't access it. The PPU reads OAM
+/// during OAM scan and both VRAM and OAM while drawing. With the LCD off the PPU doesn't touch
+/// either, so the CPU has free access.
+pub fn ppu_blocks_access(lcdc: u8, stat: u8, addr: u16) -> bool {
+    if lcdc & LCDC_LCD_ENABLE == 0 {
+        return false;
+    }
+
+    let ppu_mode = stat & 0b00000011;
+    match addr {
+        0x8000..=0x9FFF => ppu_mode == PPU_MODE_RENDER_PIXEL,
+        0xFE00..=0xFE9F => ppu_mode == PPU_MODE_OAM_SCAN || ppu_mode == PPU_MODE_RENDER_PIXEL,
+        _ => false,
+    }
+}
+
+/// CGB VRAM DMA set up through HDMA1-HDMA5
+#[derive(Default)]
+pub struct VramDma {
+    pub source: u16,
+    pub destination: u16,
+    /// 16 byte blocks left to copy
+    pub blocks_left: u8,
+    /// A general purpose transfer was requested and copies everything at once
+    pub general_requested: bool,
+    /// An HBlank transfer is running, copying one block every horizontal blank
+    pub hblank_active: bool,
+    /// Set by the PPU when it enters horizontal blank until HBlank DMA has seen it
+    pub hblank_started: bool,
+}
+
 #[derive(Default)]
 pub struct MemorySharedData {
     pub r: Registers,
     pub ime: bool,
-    // todo: CPU and PPU access to memory is restricted while a DMA transfer is active
-    pub dma_source_address: u16,
-    pub inputs: Inputs
+    pub oam_dma: OamDma,
+    pub vram_dma: VramDma,
+    pub inputs: Inputs,
+    /// Whether the cartridge's rumble motor is currently powered
+    pub rumble: bool,
+    /// Set when battery-backed cartridge memory changes, cleared once it has been saved
+    pub cart_ram_written: bool,
+    /// Run the cartridge clock forward by the real time since the save was written when loading
+    /// it. Off by default so headless and test runs are reproducible.
+    pub rtc_catch_up: bool,
+    /// Set by CPU writes to DIV/TIMA until the timer handles them
+    pub div_written: bool,
+    pub tima_written: bool,
+    /// Set by CPU writes to SC until the serial port handles them
+    pub sc_written: bool,
+    /// The CPU stops executing instructions until an interrupt is pending
+    pub halted: bool,
+    /// Set by HALT when IME is off and an interrupt is already pending. The next opcode fetch
+    /// doesn't increment PC, so the byte after HALT is read twice.
+    pub halt_bug: bool,
+    /// Low power mode entered by STOP, left when a joypad button is pressed
+    pub stopped: bool,
+    /// CPU writes to the sound registers waiting for the APU, in the order they happened
+    pub apu_writes: Vec<(u16, u8)>,
+    /// Boot ROM mapped over the start of the cartridge ROM until it's disabled through 0xFF50
+    pub boot_rom: Option<Vec<u8>>,
+    /// Running a CGB game with the CGB's extra registers and memory banks
+    pub cgb_mode: bool,
+    /// The CGB CPU, timer and DMA run at twice the normal clock. Switched by STOP when KEY1
+    /// bit 0 is set.
+    pub double_speed: bool,
+}
+
+impl MemorySharedData {
+    /// The boot ROM's byte at `addr` if it's mapped there
+    pub fn boot_rom_byte(&self, addr: u16) -> Option<u8> {
+        self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.get(addr as usize)).copied()
+    }
 }
 
 pub trait MemoryController {
     fn shared_data(&self) -> &MemorySharedData;
     fn shared_data_mut(&mut self) -> &mut MemorySharedData;
-    fn read_8(&self, addr: u16) -> u8;
+    fn internal(&self) -> &InternalMemory;
+    /// Reads memory as the CPU sees it
+    fn read_8(&self, addr: u16) -> u8 {
+        if self.cpu_access_blocked(addr) || (!self.shared_data().cgb_mode && is_cgb_register(addr)) {
+            return 0xFF;
+        }
+
+        self.read_8_sys(addr)
+    }
+
+    /// Reads memory without any of the restrictions the CPU has
     fn read_8_sys(&self, addr: u16) -> u8;
 
+    /// Whether something else is using the memory the CPU is trying to access. Blocked reads
+    /// return 0xFF and blocked writes are ignored.
+    fn cpu_access_blocked(&self, addr: u16) -> bool {
+        // OAM DMA owns the bus, the CPU can only reach IO registers and HRAM
+        if self.shared_data().oam_dma.active() && addr < 0xFF00 {
+            return true;
+        }
+
+        ppu_blocks_access(self.read_8_sys(ADDRESS_LCDC), self.read_8_sys(ADDRESS_STAT), addr)
+    }
+
+    /// Writes memory as the CPU does, including the side effects of writing IO registers
     fn write_8(&mut self, addr: u16, mut val: u8) {
+        if self.cpu_access_blocked(addr) {
+            return;
+        }
+
         match addr {
             ADDRESS_JOYP => {
                 let joyp_orig = self.read_8_sys(ADDRESS_JOYP);
@@ -135,8 +252,17 @@ pub trait MemoryController {
                 return;
             },
             ADDRESS_DIV => {
+                self.shared_data_mut().div_written = true;
                 val = 0;
             },
+            ADDRESS_TIMA => {
+                self.shared_data_mut().tima_written = true;
+            },
+            ADDRESS_SC => {
+                self.shared_data_mut().sc_written = true;
+                // Bit 1 selects the fast clock on the CGB, the other middle bits don't exist
+                val |= if self.shared_data().cgb_mode { 0x7C } else { 0x7E };
+            },
             ADDRESS_STAT => {
                 let stat = self.read_8_sys(ADDRESS_STAT);
                 // Bits 0, 1, and 2 are read-only for the CPU
@@ -146,8 +272,86 @@ pub trait MemoryController {
                 // LY is read-only
                 return;
             },
+            ADDRESS_BOOT_ROM_DISABLE if val != 0 => {
+                // The boot ROM can't be mapped back in once it's gone
+                self.shared_data_mut().boot_rom = None;
+            },
+            _ if !self.shared_data().cgb_mode && is_cgb_register(addr) => {
+                // The CGB registers don't exist on the DMG
+                return;
+            },
+            ADDRESS_KEY1 => {
+                // Only the switch armed bit can be written, the current speed is read-only
+                val = (self.read_8_sys(ADDRESS_KEY1) & 0x80) | (val & 1) | 0x7E;
+            },
+            ADDRESS_VBK => {
+                val |= 0xFE;
+            },
+            ADDRESS_SVBK => {
+                val |= 0xF8;
+            },
+            ADDRESS_HDMA1..=ADDRESS_HDMA4 => {
+                let dma = &mut self.shared_data_mut().vram_dma;
+                match addr {
+                    ADDRESS_HDMA1 => dma.source = (dma.source & 0x00FF) | (val as u16) << 8,
+                    // The low 4 bits are ignored, transfers are always 16 byte aligned
+                    ADDRESS_HDMA2 => dma.source = (dma.source & 0xFF00) | (val & 0xF0) as u16,
+                    // The destination is always in VRAM
+                    ADDRESS_HDMA3 => dma.destination = 0x8000 | (dma.destination & 0x00FF) | ((val & 0x1F) as u16) << 8,
+                    _ => dma.destination = 0x8000 | (dma.destination & 0xFF00) | (val & 0xF0) as u16,
+                }
+                // These registers are write-only
+                val = 0xFF;
+            },
+            ADDRESS_HDMA5 => {
+                let lcd_enabled = self.read_8_sys(ADDRESS_LCDC) & LCDC_LCD_ENABLE != 0;
+                let dma = &mut self.shared_data_mut().vram_dma;
+                if dma.hblank_active && val & 0x80 == 0 {
+                    // Clearing bit 7 during an HBlank transfer stops it, the length left can still
+                    // be read
+                    dma.hblank_active = false;
+                    val = 0x80 | (dma.blocks_left - 1);
+                } else {
+                    dma.blocks_left = (val & 0x7F) + 1;
+                    if val & 0x80 != 0 {
+                        dma.hblank_active = true;
+                        // With the LCD off there are no horizontal blanks, one block is copied
+                        // straight away
+                        dma.hblank_started = !lcd_enabled;
+                    } else {
+                        dma.general_requested = true;
+                    }
+                    // Bit 7 reads 0 while a transfer is running
+                    val &= 0x7F;
+                }
+            },
+            ADDRESS_BCPS | ADDRESS_OCPS => {
+                val |= 0x40;
+            },
+            ADDRESS_BCPD | ADDRESS_OCPD => {
+                self.write_8_sys(addr, val);
+                // Bit 7 of BCPS/OCPS moves the index on to the next byte after every write
+                let spec_addr = addr - 1;
+                let spec = self.read_8_sys(spec_addr);
+                if spec & 0x80 != 0 {
+                    self.write_8_sys(spec_addr, (spec & 0xC0) | (spec.wrapping_add(1) & 0x3F));
+                }
+                return;
+            },
             ADDRESS_DMA_CONTROL => {
-                self.shared_data_mut().dma_source_address = val as u16 * 0x100;
+                self.shared_data_mut().oam_dma.requested = Some((val as u16 * 0x100, OAM_DMA_START_DELAY));
+            },
+            ADDRESS_NR10..=ADDRESS_NR52 => {
+                let nr52 = self.read_8_sys(ADDRESS_NR52);
+                if addr == ADDRESS_NR52 {
+                    // The channel status bits are read-only
+                    val = (val & 0x80) | (nr52 & 0x0F);
+                } else if (nr52 & 0x80) == 0 {
+                    // Registers can't be written while the APU is powered off
+                    return;
+                }
+                self.shared_data_mut().apu_writes.push((addr, val));
+                val |= apu::register_read_mask(addr);
             },
             _ => {},
         }
@@ -155,9 +359,8 @@ pub trait MemoryController {
         self.write_8_sys(addr, val);
     }
     
+    /// Writes memory without any of the restrictions or side effects CPU writes have
     fn write_8_sys(&mut self, addr: u16, val: u8);
-    // remove?
-    fn mut_8(&mut self, addr: u16) -> &mut u8;
     fn r(&mut self) -> &mut Registers {
         &mut self.shared_data_mut().r
     }
@@ -168,54 +371,65 @@ pub trait MemoryController {
         &mut self.shared_data_mut().ime
     }
 
+    /// Advances clocks that live on the cartridge, like the MBC3 real-time clock, by the given
+    /// number of machine cycles
+    fn tick_cartridge(&mut self, _cycles: u64) {}
+
+    /// Cartridge RAM (and RTC state) in the raw dump format other emulators use for .sav files
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores data produced by `save_data`
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
     fn process_input(&mut self) {
         let joyp_orig = self.read_8_sys(ADDRESS_JOYP);
         let input = &self.shared_data().inputs;
 
+        // Buttons and select lines are active low
         let mut joyp_new = joyp_orig | 0x0f;
-        if (joyp_new & 0x30) != 0x30 {
-            if joyp_new & (1 << 4) != 0 {
-                if input.right {
-                    joyp_new &= !(1);
-                }
-    
-                if input.left {
-                    joyp_new &= !(1 << 1);
-                }
-    
-                if input.up {
-                    joyp_new &= !(1 << 2);
-                }
-    
-                if input.down {
-                    joyp_new &= !(1 << 3);
-                }
+        if joyp_new & (1 << 4) == 0 {
+            if input.right {
+                joyp_new &= !(1);
             }
-            
-            if joyp_new & (1 << 5) != 0 {
-                if input.a {
-                    joyp_new &= !(1);
-                }
-    
-                if input.b {
-                    joyp_new &= !(1 << 1);
-                }
-    
-                if input.select {
-                    joyp_new &= !(1 << 2);
-                }
-    
-                if input.start {
-                    joyp_new &= !(1 << 3);
-                }
+
+            if input.left {
+                joyp_new &= !(1 << 1);
+            }
+
+            if input.up {
+                joyp_new &= !(1 << 2);
+            }
+
+            if input.down {
+                joyp_new &= !(1 << 3);
+            }
+        }
+
+        if joyp_new & (1 << 5) == 0 {
+            if input.a {
+                joyp_new &= !(1);
+            }
+
+            if input.b {
+                joyp_new &= !(1 << 1);
+            }
+
+            if input.select {
+                joyp_new &= !(1 << 2);
+            }
+
+            if input.start {
+                joyp_new &= !(1 << 3);
             }
         }
 
         self.write_8_sys(ADDRESS_JOYP, joyp_new);
 
-        let interrupt_request = self.read_8_sys(ADDRESS_IF);
-        if (joyp_new & 0x0f) != 0x0f && joyp_new != joyp_orig {
-            // it seems like IF is not cleared if the button is no longer held. todo figure out?
+        // The interrupt is requested when any input line goes from high to low
+        if (joyp_orig & !joyp_new & 0x0f) != 0 {
+            let interrupt_request = self.read_8_sys(ADDRESS_IF);
             self.write_8_sys(ADDRESS_IF, interrupt_request | (1 << 4));
         }
     }
@@ -223,7 +437,11 @@ pub trait MemoryController {
 
 #[cfg(test)]
 mod tests {
-    use super::RegisterPair;
+    use crate::{constants::*, debug::metrics::DebugMetrics, memory_controllers::basic_memory::BasicMemory, opcodes::process_instruction};
+
+    use rstest::rstest;
+
+    use super::{ppu_blocks_access, MemoryController, RegisterPair};
 
     #[test]
     fn register_pair_uinc_16_1() {
@@ -278,4 +496,129 @@ mod tests {
 
         assert_eq!(0xFFFF, r.r16())
     }
+
+    #[test]
+    fn joypad_reads_selected_group() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().inputs.start = true;
+        m.shared_data_mut().inputs.up = true;
+
+        m.write_8(ADDRESS_JOYP, 0x10);
+        assert_eq!(0x17, m.read_8(ADDRESS_JOYP) & 0x3F);
+        m.write_8(ADDRESS_JOYP, 0x20);
+        assert_eq!(0x2B, m.read_8(ADDRESS_JOYP) & 0x3F);
+        m.write_8(ADDRESS_JOYP, 0x30);
+        assert_eq!(0x3F, m.read_8(ADDRESS_JOYP) & 0x3F);
+    }
+
+    #[test]
+    fn joypad_interrupt_only_on_press() {
+        let mut m = BasicMemory::default();
+        m.write_8(ADDRESS_JOYP, 0x20);
+        m.shared_data_mut().inputs.left = true;
+        m.shared_data_mut().inputs.right = true;
+        m.process_input();
+        assert_ne!(0, m.read_8(ADDRESS_IF) & 0x10);
+
+        m.write_8(ADDRESS_IF, 0);
+        m.shared_data_mut().inputs.right = false;
+        m.process_input();
+        assert_eq!(0, m.read_8(ADDRESS_IF) & 0x10, "releasing a button should not request an interrupt");
+    }
+
+    #[rstest]
+    #[case(0x00, PPU_MODE_RENDER_PIXEL, 0x8000, false)]
+    #[case(0x00, PPU_MODE_RENDER_PIXEL, 0xFE00, false)]
+    #[case(0x80, PPU_MODE_RENDER_PIXEL, 0x8000, true)]
+    #[case(0x80, PPU_MODE_RENDER_PIXEL, 0x9FFF, true)]
+    #[case(0x80, PPU_MODE_RENDER_PIXEL, 0xFE9F, true)]
+    #[case(0x80, PPU_MODE_RENDER_PIXEL, 0xC000, false)]
+    #[case(0x80, PPU_MODE_OAM_SCAN, 0x8000, false)]
+    #[case(0x80, PPU_MODE_OAM_SCAN, 0xFE00, true)]
+    #[case(0x80, PPU_MODE_HORIZ_BLANK, 0xFE00, false)]
+    #[case(0x80, PPU_MODE_VERT_BLANK, 0x8000, false)]
+    fn ppu_blocks_access_test(#[case] lcdc: u8, #[case] ppu_mode: u8, #[case] addr: u16, #[case] expected_result: bool) {
+        assert_eq!(expected_result, ppu_blocks_access(lcdc, ppu_mode, addr));
+    }
+
+    #[test]
+    fn oam_locked_during_oam_scan() {
+        let mut m = BasicMemory::default();
+        m.write_8(ADDRESS_OAM_START, 0x12);
+        m.write_8_sys(ADDRESS_LCDC, 0x80);
+        m.write_8_sys(ADDRESS_STAT, PPU_MODE_OAM_SCAN);
+
+        assert_eq!(0xFF, m.read_8(ADDRESS_OAM_START));
+        m.write_8(ADDRESS_OAM_START, 0x34);
+        assert_eq!(0x12, m.read_8_sys(ADDRESS_OAM_START));
+    }
+
+    #[rstest]
+    #[case(PPU_MODE_RENDER_PIXEL, 0x8000, 0x00)]
+    #[case(PPU_MODE_OAM_SCAN, ADDRESS_OAM_START, 0x00)]
+    #[case(PPU_MODE_RENDER_PIXEL, ADDRESS_OAM_START, 0x00)]
+    #[case(PPU_MODE_HORIZ_BLANK, 0x8000, 0x99)]
+    fn hl_writes_follow_ppu_locks(#[case] mode: u8, #[case] addr: u16, #[case] expected: u8) {
+        let mut m = BasicMemory::default();
+        // LD (HL),A
+        m.write_8(0xC000, 0b01_110_111);
+        m.r().pc = 0xC000;
+        m.r().a = 0x99;
+        m.r().hl.s16(addr);
+        m.write_8_sys(ADDRESS_LCDC, 0x80);
+        m.write_8_sys(ADDRESS_STAT, mode);
+
+        process_instruction(&mut m, &mut DebugMetrics::new());
+        assert_eq!(expected, m.read_8_sys(addr));
+    }
+
+    #[test]
+    fn cgb_vram_and_wram_banks() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().cgb_mode = true;
+        m.write_8(0x8000, 0x12);
+        m.write_8(0xD000, 0x34);
+
+        m.write_8(ADDRESS_VBK, 1);
+        m.write_8(ADDRESS_SVBK, 2);
+        assert_eq!(0x00, m.read_8(0x8000));
+        assert_eq!(0x00, m.read_8(0xD000));
+        m.write_8(0x8000, 0x56);
+
+        m.write_8(ADDRESS_VBK, 0);
+        // Bank 0 selects bank 1 at 0xD000
+        m.write_8(ADDRESS_SVBK, 0);
+        assert_eq!(0x12, m.read_8(0x8000));
+        assert_eq!(0x34, m.read_8(0xD000));
+        assert_eq!(0x56, m.internal().read_vram(1, 0x8000));
+    }
+
+    #[test]
+    fn cgb_registers_ignored_on_dmg() {
+        let mut m = BasicMemory::default();
+        m.write_8(0x8000, 0x12);
+        m.write_8(ADDRESS_VBK, 1);
+        assert_eq!(0x12, m.read_8(0x8000));
+
+        for addr in [ADDRESS_KEY1, ADDRESS_VBK, ADDRESS_HDMA5, ADDRESS_SVBK, ADDRESS_BCPS, ADDRESS_OCPD] {
+            assert_eq!(0xFF, m.read_8(addr), "{:#x} reads 0xFF", addr);
+        }
+    }
+
+    #[test]
+    fn palette_data_auto_increment() {
+        let mut m = BasicMemory::default();
+        m.shared_data_mut().cgb_mode = true;
+        m.write_8(ADDRESS_BCPS, 0x80 | 0x3E);
+        m.write_8(ADDRESS_BCPD, 0x1F);
+        m.write_8(ADDRESS_BCPD, 0x00);
+        assert_eq!(0x001F, m.internal().palette_color(false, 7, 3));
+        // The index wraps around within palette RAM
+        assert_eq!(0xC0, m.read_8(ADDRESS_BCPS));
+
+        m.write_8(ADDRESS_OCPS, 0x02);
+        m.write_8(ADDRESS_OCPD, 0xE0);
+        m.write_8(ADDRESS_OCPD, 0x03);
+        assert_eq!(0x7F03, m.internal().palette_color(true, 0, 1));
+    }
 }
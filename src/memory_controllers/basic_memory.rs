@@ -1,27 +1,25 @@
-use crate::{
-    constants::*,
-    memory::{MemoryController, MemorySharedData},
-};
+use crate::memory::{MemoryController, MemorySharedData};
+
+use super::{internal_memory::InternalMemory, RAM_BANK_SIZE};
 
 #[repr(C)]
 pub struct BasicMemory {
     pub shared_data: MemorySharedData,
-    rom: Vec<u8>,       // 0x0000 - 0x7FFF
-    vram: [u8; 0x2000], // 0x8000 - 0x9FFF
-    ram: [u8; 0x2000],  // 0xC000 - 0xDFFF
-    oam: [u8; 0xA0],
-    system_mem: [u8; 0x100],
+    rom: Vec<u8>,      // 0x0000 - 0x7FFF
+    cart_ram: Vec<u8>, // 0xA000 - 0xBFFF
+    internal: InternalMemory,
 }
 
 impl BasicMemory {
-    pub fn new(rom: Vec<u8>) -> Self {
+    pub fn new(rom: Vec<u8>, has_ram: bool) -> Self {
+        // Without an MBC there's no banking, so RAM fills the whole area it's mapped to
+        let ram_size = if has_ram { RAM_BANK_SIZE } else { 0 };
+
         Self {
             shared_data: Default::default(),
             rom,
-            vram: [0; 0x2000],
-            ram: [0; 0x2000],
-            oam: [0; 0xA0],
-            system_mem: [0; 0x100],
+            cart_ram: vec![0; ram_size],
+            internal: InternalMemory::new(),
         }
     }
 }
@@ -35,98 +33,72 @@ impl MemoryController for BasicMemory {
         &mut self.shared_data
     }
 
-    fn read_8(&self, addr: u16) -> u8 {
-        self.read_8_sys(addr)
+    fn internal(&self) -> &InternalMemory {
+        &self.internal
     }
 
     fn read_8_sys(&self, addr: u16) -> u8 {
-        if addr < 0x8000 {
+        if let Some(val) = self.shared_data.boot_rom_byte(addr) {
+            val
+        } else if addr < 0x8000 {
             self.rom[addr as usize]
-        } else if addr < 0xA000 {
-            self.vram[(addr - 0x8000) as usize]
-        } else if addr < 0xC000 {
-            panic!(
-                "Tried to read cartridge RAM at {:#x} but cartridge has no RAM",
-                addr
-            )
-        } else if addr < 0xE000 {
-            self.ram[(addr - 0xC000) as usize]
-        } else if addr < 0xFE00 {
-            // Nintendo prohibits use but hardware functionality is documented as echoing C000
-            self.read_8(addr - 0x2000)
-        } else if addr < 0xFEA0 {
-            self.oam[(addr - 0xFE00) as usize]
-        } else if addr < 0xFF00 {
-            todo!(
-                "Tried to read prohibited space at {:#x}. Hardware behavior not implemented yet.",
-                addr
-            )
+        } else if (0xA000..0xC000).contains(&addr) {
+            // Open bus when there's no RAM
+            self.cart_ram.get((addr - 0xA000) as usize).copied().unwrap_or(0xFF)
         } else {
-            self.system_mem[(addr - 0xFF00) as usize]
+            self.internal.read_8(addr)
         }
     }
 
     fn write_8_sys(&mut self, addr: u16, val: u8) {
         if addr < 0x8000 {
             // writing to ROM is skipped
-        } else if addr < 0xA000 {
-            if crate::debug::flags::DEBUG_PRINT_VRAM_WRITES {
-                println!("Writing {:#b} to VRAM {:#x}", val, addr);
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(byte) = self.cart_ram.get_mut((addr - 0xA000) as usize) {
+                *byte = val;
+                self.shared_data.cart_ram_written = true;
             }
-            self.vram[(addr - 0x8000) as usize] = val;
-        } else if addr < 0xC000 {
-            panic!(
-                "Tried to write cartridge RAM at {:#x} but cartridge has no RAM",
-                addr
-            )
-        } else if addr < 0xE000 {
-            self.ram[(addr - 0xC000) as usize] = val;
-        } else if addr < 0xFE00 {
-            // Nintendo prohibits use but hardware functionality is documented as echoing C000
-            self.write_8(addr - 0x2000, val);
-        } else if addr < 0xFEA0 {
-            self.oam[(addr - 0xFE00) as usize] = val;
-        } else if addr < 0xFF00 {
-            // todo!("Tried to write prohibited space at {:#x}. Hardware behavior not implemented yet.", addr)
         } else {
-            self.system_mem[(addr - 0xFF00) as usize] = val;
+            self.internal.write_8(addr, val);
         }
     }
 
-    fn mut_8(&mut self, addr: u16) -> &mut u8 {
-        if addr < 0x8000 {
-            panic!("Tried to get mutable ref to {:#x}", addr)
-        } else if addr < 0xA000 {
-            &mut self.vram[(addr - 0x8000) as usize]
-        } else if addr < 0xC000 {
-            panic!(
-                "Tried to get mutable ref to cartridge RAM at {:#x} but cartridge has no RAM",
-                addr
-            )
-        } else if addr < 0xE000 {
-            &mut self.ram[(addr - 0xC000) as usize]
-        } else if addr < 0xFE00 {
-            // Nintendo prohibits use but hardware functionality is documented as echoing C000
-            self.mut_8(addr - 0x2000)
-        } else if addr < 0xFEA0 {
-            &mut self.oam[(addr - 0xFE00) as usize]
-        } else if addr < 0xFF00 {
-            todo!("Tried to get mutable ref to prohibited space at {:#x}. Hardware behavior not implemented yet.", addr)
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.cart_ram.is_empty() {
+            None
         } else {
-            &mut self.system_mem[(addr - 0xFF00) as usize]
+            Some(self.cart_ram.to_vec())
         }
     }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.cart_ram.len());
+        self.cart_ram[..len].copy_from_slice(&data[..len]);
+    }
 }
 
 impl Default for BasicMemory {
     fn default() -> Self {
-        Self {
-            shared_data: Default::default(),
-            rom: vec![0; 0x8000],
-            vram: [0; 0x2000],
-            ram: [0; 0x2000],
-            oam: [0; 0xA0],
-            system_mem: [0; 0x100],
-        }
+        Self::new(vec![0; 0x8000], false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::MemoryController;
+
+    use super::BasicMemory;
+
+    #[test]
+    fn ram_only_when_cartridge_has_it() {
+        let mut m = BasicMemory::new(vec![0; 0x8000], true);
+        m.write_8(0xBFFF, 0x12);
+        assert_eq!(0x12, m.read_8(0xBFFF));
+        assert_eq!(Some(0x2000), m.save_data().map(|data| data.len()));
+
+        let mut m = BasicMemory::default();
+        m.write_8(0xA000, 0x12);
+        assert_eq!(0xFF, m.read_8(0xA000));
+        assert_eq!(None, m.save_data());
     }
 }
@@ -0,0 +1,119 @@
+use crate::constants::*;
+
+/// Memory that lives inside the console rather than on the cartridge. Every memory controller
+/// owns one of these and handles the cartridge ROM and RAM ranges itself.
+///
+/// VRAM and work RAM have the extra banks of the CGB. The bank registers can only be written in
+/// CGB mode, so on the DMG they stay at bank 0 of VRAM and bank 1 of work RAM.
+#[repr(C)]
+pub struct InternalMemory {
+    vram: [u8; 0x4000], // 0x8000 - 0x9FFF, 2 banks
+    ram: [u8; 0x8000],  // 0xC000 - 0xDFFF, bank 0 then 7 switchable banks at 0xD000
+    oam: [u8; 0xA0],
+    system_mem: [u8; 0x100],
+    /// CGB colour palettes, 8 palettes of 4 little endian RGB555 colours each
+    bg_palette_ram: [u8; 0x40],
+    obj_palette_ram: [u8; 0x40],
+}
+
+impl InternalMemory {
+    pub fn new() -> Self {
+        Self {
+            vram: [0; 0x4000],
+            ram: [0; 0x8000],
+            oam: [0; 0xA0],
+            system_mem: [0; 0x100],
+            // The CGB boot ROM leaves the palettes white
+            bg_palette_ram: [0xFF; 0x40],
+            obj_palette_ram: [0xFF; 0x40],
+        }
+    }
+
+    /// Reads VRAM from either bank, no matter which one the CPU has selected
+    pub fn read_vram(&self, bank: u8, addr: u16) -> u8 {
+        self.vram[(bank & 1) as usize * 0x2000 + (addr - 0x8000) as usize]
+    }
+
+    /// RGB555 colour from palette RAM
+    pub fn palette_color(&self, obj: bool, palette: u8, color: u8) -> u16 {
+        let palette_ram = if obj { &self.obj_palette_ram } else { &self.bg_palette_ram };
+        let index = (palette as usize & 7) * 8 + (color as usize & 3) * 2;
+        u16::from_le_bytes([palette_ram[index], palette_ram[index + 1]]) & 0x7FFF
+    }
+
+    fn vram_index(&self, addr: u16) -> usize {
+        let bank = self.system_mem[(ADDRESS_VBK - 0xFF00) as usize] & 1;
+        bank as usize * 0x2000 + (addr - 0x8000) as usize
+    }
+
+    fn ram_index(&self, addr: u16) -> usize {
+        if addr < 0xD000 {
+            (addr - 0xC000) as usize
+        } else {
+            // Bank 0 can't be selected at 0xD000, it maps to bank 1 instead
+            let bank = (self.system_mem[(ADDRESS_SVBK - 0xFF00) as usize] & 7).max(1);
+            bank as usize * 0x1000 + (addr - 0xD000) as usize
+        }
+    }
+
+    /// Index into palette RAM selected by BCPS or OCPS
+    fn palette_index(&self, spec_addr: u16) -> usize {
+        (self.system_mem[(spec_addr - 0xFF00) as usize] & 0x3F) as usize
+    }
+
+    pub fn read_8(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            panic!("Tried to read cartridge ROM at {:#x} from internal memory", addr)
+        } else if addr < 0xA000 {
+            self.vram[self.vram_index(addr)]
+        } else if addr < 0xC000 {
+            panic!("Tried to read cartridge RAM at {:#x} from internal memory", addr)
+        } else if addr < 0xE000 {
+            self.ram[self.ram_index(addr)]
+        } else if addr < 0xFE00 {
+            // Nintendo prohibits use but hardware functionality is documented as echoing C000
+            self.read_8(addr - 0x2000)
+        } else if addr < 0xFEA0 {
+            self.oam[(addr - 0xFE00) as usize]
+        } else if addr < 0xFF00 {
+            todo!(
+                "Tried to read prohibited space at {:#x}. Hardware behavior not implemented yet.",
+                addr
+            )
+        } else if addr == ADDRESS_BCPD {
+            self.bg_palette_ram[self.palette_index(ADDRESS_BCPS)]
+        } else if addr == ADDRESS_OCPD {
+            self.obj_palette_ram[self.palette_index(ADDRESS_OCPS)]
+        } else {
+            self.system_mem[(addr - 0xFF00) as usize]
+        }
+    }
+
+    pub fn write_8(&mut self, addr: u16, val: u8) {
+        if addr < 0x8000 {
+            panic!("Tried to write cartridge ROM at {:#x} from internal memory", addr)
+        } else if addr < 0xA000 {
+            if crate::debug::flags::DEBUG_PRINT_VRAM_WRITES {
+                println!("Writing {:#b} to VRAM {:#x}", val, addr);
+            }
+            self.vram[self.vram_index(addr)] = val;
+        } else if addr < 0xC000 {
+            panic!("Tried to write cartridge RAM at {:#x} from internal memory", addr)
+        } else if addr < 0xE000 {
+            self.ram[self.ram_index(addr)] = val;
+        } else if addr < 0xFE00 {
+            // Nintendo prohibits use but hardware functionality is documented as echoing C000
+            self.write_8(addr - 0x2000, val);
+        } else if addr < 0xFEA0 {
+            self.oam[(addr - 0xFE00) as usize] = val;
+        } else if addr < 0xFF00 {
+            // todo!("Tried to write prohibited space at {:#x}. Hardware behavior not implemented yet.", addr)
+        } else if addr == ADDRESS_BCPD {
+            self.bg_palette_ram[self.palette_index(ADDRESS_BCPS)] = val;
+        } else if addr == ADDRESS_OCPD {
+            self.obj_palette_ram[self.palette_index(ADDRESS_OCPS)] = val;
+        } else {
+            self.system_mem[(addr - 0xFF00) as usize] = val;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::memory::{MemoryController, MemorySharedData};
+
+use super::{
+    cartridge_ram_size, internal_memory::InternalMemory, read_rom_bank, rom_bank_count,
+    RAM_BANK_SIZE,
+};
+
+// https://gbdev.io/pandocs/MBC1.html
+#[repr(C)]
+pub struct Mbc1 {
+    pub shared_data: MemorySharedData,
+    rom: Vec<u8>,      // 0x0000 - 0x7FFF
+    cart_ram: Vec<u8>, // 0xA000 - 0xBFFF
+    internal: InternalMemory,
+    rom_bank_count: usize,
+    ram_enabled: bool,
+    /// 5 bit register at 0x2000 - 0x3FFF
+    bank_low: u8,
+    /// 2 bit register at 0x4000 - 0x5FFF. Selects the RAM bank or the upper bits of the ROM bank.
+    bank_high: u8,
+    /// 1 bit register at 0x6000 - 0x7FFF
+    advanced_banking_mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let ram_size = cartridge_ram_size(&rom);
+        let rom_bank_count = rom_bank_count(&rom);
+
+        Self {
+            shared_data: Default::default(),
+            rom,
+            cart_ram: vec![0; ram_size],
+            internal: InternalMemory::new(),
+            rom_bank_count,
+            ram_enabled: false,
+            bank_low: 1,
+            bank_high: 0,
+            advanced_banking_mode: false,
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        // On 1 MiB+ carts the 2 bit register is wired to ROM address lines 19-20. In advanced
+        // banking mode it also applies to the 0x0000 - 0x3FFF area.
+        let bank = if addr < 0x4000 {
+            if self.advanced_banking_mode {
+                (self.bank_high as usize) << 5
+            } else {
+                0
+            }
+        } else {
+            ((self.bank_high as usize) << 5) | self.bank_low as usize
+        };
+
+        bank & (self.rom_bank_count - 1)
+    }
+
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.cart_ram.is_empty() {
+            return None;
+        }
+
+        let bank = if self.advanced_banking_mode {
+            self.bank_high as usize
+        } else {
+            0
+        };
+        let index = bank * RAM_BANK_SIZE + (addr - 0xA000) as usize;
+        Some(index % self.cart_ram.len())
+    }
+}
+
+impl MemoryController for Mbc1 {
+    fn shared_data(&self) -> &MemorySharedData {
+        &self.shared_data
+    }
+
+    fn shared_data_mut(&mut self) -> &mut MemorySharedData {
+        &mut self.shared_data
+    }
+
+    fn internal(&self) -> &InternalMemory {
+        &self.internal
+    }
+
+    fn read_8_sys(&self, addr: u16) -> u8 {
+        if let Some(val) = self.shared_data.boot_rom_byte(addr) {
+            val
+        } else if addr < 0x8000 {
+            read_rom_bank(&self.rom, self.rom_bank(addr), addr)
+        } else if (0xA000..0xC000).contains(&addr) {
+            match self.ram_index(addr) {
+                Some(i) => self.cart_ram[i],
+                None => 0xFF,
+            }
+        } else {
+            self.internal.read_8(addr)
+        }
+    }
+
+    fn write_8_sys(&mut self, addr: u16, val: u8) {
+        if addr < 0x2000 {
+            self.ram_enabled = (val & 0x0F) == 0x0A;
+        } else if addr < 0x4000 {
+            // 0 is treated as 1 before masking to the ROM size, so bank 0x20 can't be selected
+            // through 0x4000 - 0x7FFF
+            self.bank_low = (val & 0x1F).max(1);
+        } else if addr < 0x6000 {
+            self.bank_high = val & 3;
+        } else if addr < 0x8000 {
+            self.advanced_banking_mode = (val & 1) != 0;
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(i) = self.ram_index(addr) {
+                self.cart_ram[i] = val;
+                self.shared_data.cart_ram_written = true;
+            }
+        } else {
+            self.internal.write_8(addr, val);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.cart_ram.is_empty() {
+            None
+        } else {
+            Some(self.cart_ram.to_vec())
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.cart_ram.len());
+        self.cart_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::ADDRESS_RAM_SIZE, debug::metrics::DebugMetrics, memory::MemoryController, opcodes::process_instruction};
+
+    use super::Mbc1;
+
+    /// Every byte of a bank holds that bank's number
+    fn numbered_rom(banks: usize, ram_size_code: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks)
+            .flat_map(|b| std::iter::repeat_n(b as u8, 0x4000))
+            .collect();
+        rom[ADDRESS_RAM_SIZE as usize] = ram_size_code;
+        rom
+    }
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut m = Mbc1::new(numbered_rom(8, 0));
+        m.write_8(0x2000, 0);
+        assert_eq!(1, m.read_8(0x4000));
+        m.write_8(0x2000, 5);
+        assert_eq!(5, m.read_8(0x4000));
+    }
+
+    #[test]
+    fn bank_selected_through_hl() {
+        let mut m = Mbc1::new(numbered_rom(8, 0));
+        m.r().a = 5;
+        m.r().hl.s16(0x2000);
+        m.r().pc = 0xC000;
+        // LD (HL),A
+        m.write_8(0xC000, 0b01_110_111);
+        process_instruction(&mut m, &mut DebugMetrics::new());
+        assert_eq!(5, m.read_8(0x4000));
+    }
+
+    #[test]
+    fn rom_bank_masked_to_rom_size() {
+        let mut m = Mbc1::new(numbered_rom(8, 0));
+        m.write_8(0x2000, 0x0B);
+        assert_eq!(3, m.read_8(0x7FFF));
+    }
+
+    #[test]
+    fn large_rom_upper_bank_bits() {
+        let mut m = Mbc1::new(numbered_rom(128, 0));
+        m.write_8(0x2000, 0x02);
+        m.write_8(0x4000, 0x03);
+        assert_eq!(0x62, m.read_8(0x4000));
+        assert_eq!(0, m.read_8(0x0000));
+
+        m.write_8(0x6000, 1);
+        assert_eq!(0x60, m.read_8(0x0000));
+    }
+
+    #[test]
+    fn ram_enable_and_banking() {
+        let mut m = Mbc1::new(numbered_rom(4, 3));
+        m.write_8(0xA000, 0x12);
+        assert_eq!(0xFF, m.read_8(0xA000), "RAM should be disabled by default");
+
+        m.write_8(0x0000, 0x0A);
+        m.write_8(0xA000, 0x12);
+        m.write_8(0x6000, 1);
+        m.write_8(0x4000, 2);
+        m.write_8(0xA000, 0x34);
+        assert_eq!(0x34, m.read_8(0xA000));
+
+        m.write_8(0x4000, 0);
+        assert_eq!(0x12, m.read_8(0xA000));
+
+        m.write_8(0x0000, 0x00);
+        assert_eq!(0xFF, m.read_8(0xA000));
+    }
+}
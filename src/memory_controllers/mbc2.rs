@@ -0,0 +1,143 @@
+use crate::memory::{MemoryController, MemorySharedData};
+
+use super::{internal_memory::InternalMemory, read_rom_bank, rom_bank_count};
+
+const MBC2_RAM_SIZE: usize = 0x200;
+
+// https://gbdev.io/pandocs/MBC2.html
+#[repr(C)]
+pub struct Mbc2 {
+    pub shared_data: MemorySharedData,
+    rom: Vec<u8>, // 0x0000 - 0x7FFF
+    /// 512 half-bytes built into the MBC, only the lower 4 bits of each byte are used.
+    /// Mirrored through 0xA000 - 0xBFFF.
+    cart_ram: [u8; MBC2_RAM_SIZE],
+    internal: InternalMemory,
+    rom_bank_count: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let rom_bank_count = rom_bank_count(&rom);
+
+        Self {
+            shared_data: Default::default(),
+            rom,
+            cart_ram: [0; MBC2_RAM_SIZE],
+            internal: InternalMemory::new(),
+            rom_bank_count,
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if self.ram_enabled {
+            Some((addr as usize) & (MBC2_RAM_SIZE - 1))
+        } else {
+            None
+        }
+    }
+}
+
+impl MemoryController for Mbc2 {
+    fn shared_data(&self) -> &MemorySharedData {
+        &self.shared_data
+    }
+
+    fn shared_data_mut(&mut self) -> &mut MemorySharedData {
+        &mut self.shared_data
+    }
+
+    fn internal(&self) -> &InternalMemory {
+        &self.internal
+    }
+
+    fn read_8_sys(&self, addr: u16) -> u8 {
+        if let Some(val) = self.shared_data.boot_rom_byte(addr) {
+            val
+        } else if addr < 0x4000 {
+            read_rom_bank(&self.rom, 0, addr)
+        } else if addr < 0x8000 {
+            let bank = self.rom_bank as usize & (self.rom_bank_count - 1);
+            read_rom_bank(&self.rom, bank, addr)
+        } else if (0xA000..0xC000).contains(&addr) {
+            match self.ram_index(addr) {
+                // the upper nibble is not connected and reads as 1s
+                Some(i) => self.cart_ram[i] | 0xF0,
+                None => 0xFF,
+            }
+        } else {
+            self.internal.read_8(addr)
+        }
+    }
+
+    fn write_8_sys(&mut self, addr: u16, val: u8) {
+        if addr < 0x4000 {
+            // Bit 8 of the address selects between the two registers
+            if (addr & 0x100) == 0 {
+                self.ram_enabled = (val & 0x0F) == 0x0A;
+            } else {
+                self.rom_bank = (val & 0x0F).max(1);
+            }
+        } else if addr < 0x8000 {
+            // unmapped
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(i) = self.ram_index(addr) {
+                self.cart_ram[i] = val & 0x0F;
+                self.shared_data.cart_ram_written = true;
+            }
+        } else {
+            self.internal.write_8(addr, val);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.cart_ram.to_vec())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (ram, val) in self.cart_ram.iter_mut().zip(data) {
+            *ram = val & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::MemoryController;
+
+    use super::Mbc2;
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut rom = vec![0; 0x4000 * 16];
+        rom[0x4000 * 5] = 5;
+        let mut m = Mbc2::new(rom);
+
+        m.write_8(0x0000, 0x05);
+        assert_eq!(0, m.read_8(0x4000), "bit 8 clear should not change the ROM bank");
+
+        m.write_8(0x2100, 0x05);
+        assert_eq!(5, m.read_8(0x4000));
+
+        m.write_8(0x0100, 0x0A);
+        assert_eq!(0xFF, m.read_8(0xA000), "bit 8 set should not enable RAM");
+        m.write_8(0x3E00, 0x0A);
+        m.write_8(0xA000, 0x03);
+        assert_eq!(0xF3, m.read_8(0xA000));
+    }
+
+    #[test]
+    fn ram_is_mirrored_with_upper_nibble_set() {
+        let mut m = Mbc2::new(vec![0; 0x8000]);
+        m.write_8(0x0000, 0x0A);
+        m.write_8(0xA001, 0xA7);
+
+        assert_eq!(0xF7, m.read_8(0xA001));
+        assert_eq!(0xF7, m.read_8(0xA201));
+        assert_eq!(0xF7, m.read_8(0xBE01));
+    }
+}
@@ -0,0 +1,506 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::memory::{MemoryController, MemorySharedData};
+
+use super::{
+    cartridge_ram_size, internal_memory::InternalMemory, read_rom_bank, rom_bank_count,
+    RAM_BANK_SIZE,
+};
+
+/// Machine cycles per emulated second. The RTC counts these instead of wall-clock time so runs
+/// are deterministic.
+pub const RTC_CYCLES_PER_SECOND: u64 = 1 << 20;
+
+pub const RTC_SAVE_FOOTER_LEN: usize = 48;
+
+const RTC_REGISTER_SECONDS: u8 = 0x08;
+const RTC_REGISTER_DAY_HIGH: u8 = 0x0C;
+const RTC_DAY_HIGH_HALT: u8 = 1 << 6;
+const RTC_DAY_HIGH_CARRY: u8 = 1 << 7;
+
+// https://gbdev.io/pandocs/MBC3.html
+#[derive(Default)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// 9 bit day counter
+    pub days: u16,
+    pub halt: bool,
+    pub day_carry: bool,
+    /// Machine cycles since the seconds counter last advanced
+    pub subsecond_cycles: u64,
+    /// S, M, H, DL, DH as they were when the clock was last latched
+    pub latched: [u8; 5],
+    /// Set after 0x00 is written to the latch register so a following 0x01 latches the clock
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn tick(&mut self, cycles: u64) {
+        if self.halt {
+            return;
+        }
+
+        self.subsecond_cycles += cycles;
+        let seconds = self.subsecond_cycles / RTC_CYCLES_PER_SECOND;
+        self.subsecond_cycles %= RTC_CYCLES_PER_SECOND;
+        self.advance_seconds(seconds);
+    }
+
+    /// Runs the clock forward without stepping through every second, so catching up on a save
+    /// from years ago is as quick as a frame
+    fn advance_seconds(&mut self, mut seconds: u64) {
+        // Out of range counters don't carry normally, step them until they've wrapped around
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.advance_second();
+            seconds -= 1;
+        }
+
+        let total = self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+        let days = self.days as u64 + total / 24;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    /// Each counter only carries when it reaches its normal limit. Out of range values written by
+    /// the game count up to the register's bit width and wrap to 0 without carrying.
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+
+    pub fn registers(&self) -> [u8; 5] {
+        let mut day_high = (self.days >> 8) as u8;
+        if self.halt {
+            day_high |= RTC_DAY_HIGH_HALT;
+        }
+        if self.day_carry {
+            day_high |= RTC_DAY_HIGH_CARRY;
+        }
+
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            day_high,
+        ]
+    }
+
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = val == 0;
+    }
+
+    pub fn read_register(&self, register: u8) -> u8 {
+        let val = self.latched[(register - RTC_REGISTER_SECONDS) as usize];
+        // unused bits read as 1
+        match register {
+            0x08 | 0x09 => val | 0xC0,
+            0x0A => val | 0xE0,
+            RTC_REGISTER_DAY_HIGH => val | 0x3E,
+            _ => val,
+        }
+    }
+
+    pub fn write_register(&mut self, register: u8, val: u8) {
+        match register {
+            0x08 => {
+                self.seconds = val & 0x3F;
+                // writing seconds resets the internal divider
+                self.subsecond_cycles = 0;
+            }
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | val as u16,
+            RTC_REGISTER_DAY_HIGH => {
+                self.days = (self.days & 0xFF) | ((val as u16 & 1) << 8);
+                self.halt = (val & RTC_DAY_HIGH_HALT) != 0;
+                self.day_carry = (val & RTC_DAY_HIGH_CARRY) != 0;
+            }
+            _ => panic!("Invalid RTC register {:#x}", register),
+        }
+    }
+
+    /// The 48 byte block that VBA-M, BGB and others append to MBC3 .sav files: the live and latched
+    /// registers as little-endian u32s followed by a 64 bit UNIX timestamp of when it was saved.
+    pub fn save_footer(&self) -> [u8; RTC_SAVE_FOOTER_LEN] {
+        let mut footer = [0; RTC_SAVE_FOOTER_LEN];
+        let values = self.registers().into_iter().chain(self.latched);
+        for (i, val) in values.enumerate() {
+            footer[i * 4..i * 4 + 4].copy_from_slice(&(val as u32).to_le_bytes());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        footer[40..48].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    /// Restores a footer written by `save_footer`. With `catch_up` the clock is run forward by the
+    /// time that has passed since, as the cartridge's clock would have kept running while the
+    /// console was off. Some emulators write a 32 bit timestamp, so 44 byte footers are accepted too.
+    /// A timestamp of 0 or one in the future can't be trusted, so the clock is left alone.
+    pub fn load_save_footer(&mut self, footer: &[u8], catch_up: bool) {
+        if footer.len() < 44 {
+            println!("Ignoring RTC save data with unexpected length {}", footer.len());
+            return;
+        }
+
+        let value = |i: usize| footer[i * 4];
+        for i in 0..5 {
+            self.write_register(RTC_REGISTER_SECONDS + i as u8, value(i));
+            self.latched[i] = value(i + 5);
+        }
+
+        if !catch_up {
+            return;
+        }
+
+        let saved_at = if footer.len() >= RTC_SAVE_FOOTER_LEN {
+            u64::from_le_bytes(footer[40..48].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64
+        };
+        let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()) else {
+            return;
+        };
+        if saved_at == 0 || saved_at > now {
+            return;
+        }
+        self.tick((now - saved_at) * RTC_CYCLES_PER_SECOND);
+    }
+}
+
+#[repr(C)]
+pub struct Mbc3 {
+    pub shared_data: MemorySharedData,
+    rom: Vec<u8>,      // 0x0000 - 0x7FFF
+    cart_ram: Vec<u8>, // 0xA000 - 0xBFFF
+    internal: InternalMemory,
+    rom_bank_count: usize,
+    pub rtc: Option<Rtc>,
+    ram_and_timer_enabled: bool,
+    rom_bank: u8,
+    /// 0x00 - 0x03 map a RAM bank, 0x08 - 0x0C map an RTC register
+    ram_bank_or_rtc_register: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, has_rtc: bool) -> Self {
+        let ram_size = cartridge_ram_size(&rom);
+        let rom_bank_count = rom_bank_count(&rom);
+
+        Self {
+            shared_data: Default::default(),
+            rom,
+            cart_ram: vec![0; ram_size],
+            internal: InternalMemory::new(),
+            rom_bank_count,
+            rtc: if has_rtc { Some(Rtc::default()) } else { None },
+            ram_and_timer_enabled: false,
+            rom_bank: 1,
+            ram_bank_or_rtc_register: 0,
+        }
+    }
+
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.ram_and_timer_enabled
+            || self.cart_ram.is_empty()
+            || self.ram_bank_or_rtc_register > 3
+        {
+            return None;
+        }
+
+        let index = self.ram_bank_or_rtc_register as usize * RAM_BANK_SIZE + (addr - 0xA000) as usize;
+        Some(index % self.cart_ram.len())
+    }
+
+    fn selected_rtc_register(&self) -> Option<u8> {
+        if self.ram_and_timer_enabled
+            && self.rtc.is_some()
+            && (RTC_REGISTER_SECONDS..=RTC_REGISTER_DAY_HIGH).contains(&self.ram_bank_or_rtc_register)
+        {
+            Some(self.ram_bank_or_rtc_register)
+        } else {
+            None
+        }
+    }
+}
+
+impl MemoryController for Mbc3 {
+    fn shared_data(&self) -> &MemorySharedData {
+        &self.shared_data
+    }
+
+    fn shared_data_mut(&mut self) -> &mut MemorySharedData {
+        &mut self.shared_data
+    }
+
+    fn internal(&self) -> &InternalMemory {
+        &self.internal
+    }
+
+    fn read_8_sys(&self, addr: u16) -> u8 {
+        if let Some(val) = self.shared_data.boot_rom_byte(addr) {
+            val
+        } else if addr < 0x4000 {
+            read_rom_bank(&self.rom, 0, addr)
+        } else if addr < 0x8000 {
+            let bank = self.rom_bank as usize & (self.rom_bank_count - 1);
+            read_rom_bank(&self.rom, bank, addr)
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(register) = self.selected_rtc_register() {
+                return self.rtc.as_ref().unwrap().read_register(register);
+            }
+
+            match self.ram_index(addr) {
+                Some(i) => self.cart_ram[i],
+                None => 0xFF,
+            }
+        } else {
+            self.internal.read_8(addr)
+        }
+    }
+
+    fn write_8_sys(&mut self, addr: u16, val: u8) {
+        if addr < 0x2000 {
+            self.ram_and_timer_enabled = (val & 0x0F) == 0x0A;
+        } else if addr < 0x4000 {
+            self.rom_bank = (val & 0x7F).max(1);
+        } else if addr < 0x6000 {
+            self.ram_bank_or_rtc_register = val & 0x0F;
+        } else if addr < 0x8000 {
+            if let Some(rtc) = self.rtc.as_mut() {
+                rtc.write_latch(val);
+            }
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(register) = self.selected_rtc_register() {
+                self.rtc.as_mut().unwrap().write_register(register, val);
+                self.shared_data.cart_ram_written = true;
+            } else if let Some(i) = self.ram_index(addr) {
+                self.cart_ram[i] = val;
+                self.shared_data.cart_ram_written = true;
+            }
+        } else {
+            self.internal.write_8(addr, val);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        let mut data = self.cart_ram.clone();
+        if let Some(rtc) = self.rtc.as_ref() {
+            data.extend_from_slice(&rtc.save_footer());
+        }
+
+        if data.is_empty() {
+            None
+        } else {
+            Some(data)
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.cart_ram.len());
+        self.cart_ram[..len].copy_from_slice(&data[..len]);
+
+        if let Some(rtc) = self.rtc.as_mut() {
+            if let Some(footer) = data.get(self.cart_ram.len()..) {
+                rtc.load_save_footer(footer, self.shared_data.rtc_catch_up);
+            }
+        }
+    }
+
+    fn tick_cartridge(&mut self, cycles: u64) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(cycles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{constants::ADDRESS_RAM_SIZE, memory::MemoryController};
+
+    use super::{Mbc3, Rtc, RTC_CYCLES_PER_SECOND, RTC_SAVE_FOOTER_LEN};
+
+    fn rtc_cart() -> Mbc3 {
+        let mut m = Mbc3::new(vec![0; 0x8000], true);
+        m.write_8(0x0000, 0x0A);
+        m
+    }
+
+    fn latch(m: &mut Mbc3) {
+        m.write_8(0x6000, 0);
+        m.write_8(0x6000, 1);
+    }
+
+    fn read_rtc(m: &mut Mbc3, register: u8) -> u8 {
+        m.write_8(0x4000, register);
+        m.read_8(0xA000)
+    }
+
+    #[test]
+    fn rtc_counts_emulated_seconds() {
+        let mut m = rtc_cart();
+        m.tick_cartridge(RTC_CYCLES_PER_SECOND * 61 + 5);
+        latch(&mut m);
+
+        assert_eq!(1, read_rtc(&mut m, 0x08) & 0x3F);
+        assert_eq!(1, read_rtc(&mut m, 0x09) & 0x3F);
+    }
+
+    #[test]
+    fn rtc_registers_only_change_on_latch() {
+        let mut m = rtc_cart();
+        latch(&mut m);
+        m.tick_cartridge(RTC_CYCLES_PER_SECOND * 3);
+        assert_eq!(0, read_rtc(&mut m, 0x08) & 0x3F);
+
+        latch(&mut m);
+        assert_eq!(3, read_rtc(&mut m, 0x08) & 0x3F);
+    }
+
+    #[test]
+    fn rtc_halt_stops_clock() {
+        let mut m = rtc_cart();
+        m.write_8(0x4000, 0x0C);
+        m.write_8(0xA000, 1 << 6);
+        m.tick_cartridge(RTC_CYCLES_PER_SECOND * 3);
+        latch(&mut m);
+
+        assert_eq!(0, read_rtc(&mut m, 0x08) & 0x3F);
+    }
+
+    #[test]
+    fn rtc_day_counter_overflow_sets_carry() {
+        let mut m = rtc_cart();
+        m.write_8(0x4000, 0x0B);
+        m.write_8(0xA000, 0xFF);
+        m.write_8(0x4000, 0x0C);
+        m.write_8(0xA000, 1);
+        m.write_8(0x4000, 0x0A);
+        m.write_8(0xA000, 23);
+        m.write_8(0x4000, 0x09);
+        m.write_8(0xA000, 59);
+        m.write_8(0x4000, 0x08);
+        m.write_8(0xA000, 59);
+
+        m.tick_cartridge(RTC_CYCLES_PER_SECOND);
+        latch(&mut m);
+
+        assert_eq!(0, read_rtc(&mut m, 0x0B));
+        let day_high = read_rtc(&mut m, 0x0C);
+        assert_eq!(0, day_high & 1);
+        assert_ne!(0, day_high & 0x80);
+    }
+
+    #[test]
+    fn save_data_round_trip_with_rtc_footer() {
+        let mut rom = vec![0; 0x8000];
+        rom[ADDRESS_RAM_SIZE as usize] = 2;
+        let mut m = Mbc3::new(rom.clone(), true);
+        m.write_8(0x0000, 0x0A);
+        m.write_8(0x4000, 0x00);
+        m.write_8(0xA123, 0x77);
+        m.write_8(0x4000, 0x0A);
+        m.write_8(0xA000, 13);
+
+        let data = m.save_data().unwrap();
+        assert_eq!(0x2000 + RTC_SAVE_FOOTER_LEN, data.len());
+
+        let mut loaded = Mbc3::new(rom, true);
+        loaded.load_save_data(&data);
+        loaded.write_8(0x0000, 0x0A);
+        loaded.write_8(0x4000, 0x00);
+        assert_eq!(0x77, loaded.read_8(0xA123));
+        latch(&mut loaded);
+        assert_eq!(13, read_rtc(&mut loaded, 0x0A) & 0x1F);
+    }
+
+    #[rstest]
+    #[case(false, 0)]
+    #[case(true, 0x80)]
+    fn rtc_catch_up_only_when_enabled(#[case] catch_up: bool, #[case] expected_overflow: u8) {
+        let mut m = Mbc3::new(vec![0; 0x8000], true);
+        m.shared_data.rtc_catch_up = catch_up;
+        // Saved long enough ago to overflow the 9 bit day counter
+        let mut data = m.save_data().unwrap();
+        let saved_at = u64::from_le_bytes(data[40..48].try_into().unwrap()) - 600 * 24 * 60 * 60;
+        data[40..48].copy_from_slice(&saved_at.to_le_bytes());
+        m.load_save_data(&data);
+
+        m.write_8(0x0000, 0x0A);
+        latch(&mut m);
+        assert_eq!(expected_overflow, read_rtc(&mut m, 0x0C) & 0x80);
+    }
+
+    #[rstest]
+    #[case::zero(|_| 0)]
+    #[case::future(|now| now + 60 * 60)]
+    fn rtc_catch_up_ignores_bad_timestamp(#[case] timestamp: fn(u64) -> u64) {
+        let mut m = Mbc3::new(vec![0; 0x8000], true);
+        m.shared_data.rtc_catch_up = true;
+        let mut data = m.save_data().unwrap();
+        let now = u64::from_le_bytes(data[40..48].try_into().unwrap());
+        data[40..48].copy_from_slice(&timestamp(now).to_le_bytes());
+        m.load_save_data(&data);
+
+        assert_eq!([0; 5], m.rtc.unwrap().registers());
+    }
+
+    #[test]
+    fn rtc_ticks_decades_at_once() {
+        let mut rtc = Rtc::default();
+        let elapsed = ((10 * 365 * 24 + 5) * 60 + 6) * 60 + 7;
+        rtc.tick(elapsed * RTC_CYCLES_PER_SECOND + 3);
+
+        assert_eq!([7, 6, 5], [rtc.seconds, rtc.minutes, rtc.hours]);
+        assert_eq!((10 * 365) % 512, rtc.days);
+        assert!(rtc.day_carry);
+        assert_eq!(3, rtc.subsecond_cycles);
+    }
+
+    #[test]
+    fn rtc_out_of_range_counter_wraps_without_carry() {
+        let mut rtc = Rtc { seconds: 62, minutes: 59, ..Default::default() };
+        rtc.tick(RTC_CYCLES_PER_SECOND * 63);
+
+        // 62, 63, then 0 without carrying into the minutes, then 61 normal seconds
+        assert_eq!([1, 0, 1], [rtc.seconds, rtc.minutes, rtc.hours]);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::memory::{MemoryController, MemorySharedData};
+
+use super::{
+    cartridge_ram_size, internal_memory::InternalMemory, read_rom_bank, rom_bank_count,
+    RAM_BANK_SIZE,
+};
+
+const RUMBLE_MOTOR_BIT: u8 = 1 << 3;
+
+// https://gbdev.io/pandocs/MBC5.html
+#[repr(C)]
+pub struct Mbc5 {
+    pub shared_data: MemorySharedData,
+    rom: Vec<u8>,      // 0x0000 - 0x7FFF
+    cart_ram: Vec<u8>, // 0xA000 - 0xBFFF
+    internal: InternalMemory,
+    rom_bank_count: usize,
+    has_rumble: bool,
+    ram_enabled: bool,
+    /// 9 bit ROM bank. Unlike MBC1 and MBC3, bank 0 can be mapped to 0x4000 - 0x7FFF.
+    rom_bank: u16,
+    ram_bank: u8,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, has_rumble: bool) -> Self {
+        let ram_size = cartridge_ram_size(&rom);
+        let rom_bank_count = rom_bank_count(&rom);
+
+        Self {
+            shared_data: Default::default(),
+            rom,
+            cart_ram: vec![0; ram_size],
+            internal: InternalMemory::new(),
+            rom_bank_count,
+            has_rumble,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.cart_ram.is_empty() {
+            return None;
+        }
+
+        let index = self.ram_bank as usize * RAM_BANK_SIZE + (addr - 0xA000) as usize;
+        Some(index % self.cart_ram.len())
+    }
+}
+
+impl MemoryController for Mbc5 {
+    fn shared_data(&self) -> &MemorySharedData {
+        &self.shared_data
+    }
+
+    fn shared_data_mut(&mut self) -> &mut MemorySharedData {
+        &mut self.shared_data
+    }
+
+    fn internal(&self) -> &InternalMemory {
+        &self.internal
+    }
+
+    fn read_8_sys(&self, addr: u16) -> u8 {
+        if let Some(val) = self.shared_data.boot_rom_byte(addr) {
+            val
+        } else if addr < 0x4000 {
+            read_rom_bank(&self.rom, 0, addr)
+        } else if addr < 0x8000 {
+            let bank = self.rom_bank as usize & (self.rom_bank_count - 1);
+            read_rom_bank(&self.rom, bank, addr)
+        } else if (0xA000..0xC000).contains(&addr) {
+            match self.ram_index(addr) {
+                Some(i) => self.cart_ram[i],
+                None => 0xFF,
+            }
+        } else {
+            self.internal.read_8(addr)
+        }
+    }
+
+    fn write_8_sys(&mut self, addr: u16, val: u8) {
+        if addr < 0x2000 {
+            self.ram_enabled = (val & 0x0F) == 0x0A;
+        } else if addr < 0x3000 {
+            self.rom_bank = (self.rom_bank & 0x100) | val as u16;
+        } else if addr < 0x4000 {
+            self.rom_bank = (self.rom_bank & 0xFF) | ((val as u16 & 1) << 8);
+        } else if addr < 0x6000 {
+            if self.has_rumble {
+                // The motor is wired to bit 3 so rumble carts only have 8 RAM banks
+                self.shared_data.rumble = (val & RUMBLE_MOTOR_BIT) != 0;
+                self.ram_bank = val & 0x07;
+            } else {
+                self.ram_bank = val & 0x0F;
+            }
+        } else if addr < 0x8000 {
+            // unmapped
+        } else if (0xA000..0xC000).contains(&addr) {
+            if let Some(i) = self.ram_index(addr) {
+                self.cart_ram[i] = val;
+                self.shared_data.cart_ram_written = true;
+            }
+        } else {
+            self.internal.write_8(addr, val);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.cart_ram.is_empty() {
+            None
+        } else {
+            Some(self.cart_ram.to_vec())
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.cart_ram.len());
+        self.cart_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::ADDRESS_RAM_SIZE, memory::MemoryController};
+
+    use super::Mbc5;
+
+    /// The first byte of each bank holds the low byte of the bank number, the second the high byte
+    fn numbered_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for b in 0..banks {
+            rom[b * 0x4000] = b as u8;
+            rom[b * 0x4000 + 1] = (b >> 8) as u8;
+        }
+        rom[ADDRESS_RAM_SIZE as usize] = 4;
+        rom
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut m = Mbc5::new(numbered_rom(512), false);
+        m.write_8(0x2000, 0x23);
+        m.write_8(0x3000, 1);
+        assert_eq!(0x23, m.read_8(0x4000));
+        assert_eq!(1, m.read_8(0x4001));
+
+        m.write_8(0x2000, 0);
+        m.write_8(0x3000, 0);
+        assert_eq!(0, m.read_8(0x4000), "MBC5 can map bank 0 to 0x4000");
+    }
+
+    #[test]
+    fn rumble_uses_ram_bank_bit_3() {
+        let mut m = Mbc5::new(numbered_rom(4), true);
+        m.write_8(0x0000, 0x0A);
+        m.write_8(0x4000, 0x01);
+        m.write_8(0xA000, 0x42);
+
+        m.write_8(0x4000, 0x09);
+        assert!(m.shared_data().rumble);
+        assert_eq!(0x42, m.read_8(0xA000));
+
+        m.write_8(0x4000, 0x01);
+        assert!(!m.shared_data().rumble);
+    }
+}
@@ -1 +1,33 @@
 pub mod basic_memory;
+pub mod internal_memory;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+
+use crate::{cartridge_header::ram_size_from_code, constants::ADDRESS_RAM_SIZE};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// Size in bytes of the external RAM declared by the cartridge header. `boot` validates the header
+/// before creating a controller, so an invalid code here is a bug.
+pub fn cartridge_ram_size(rom: &[u8]) -> usize {
+    let code = rom[ADDRESS_RAM_SIZE as usize];
+    match ram_size_from_code(code) {
+        Some(size) => size,
+        None => panic!("Invalid cartridge RAM size code {:#x}", code),
+    }
+}
+
+/// Number of 16 KiB ROM banks, rounded up to a power of two so bank numbers can be masked
+pub fn rom_bank_count(rom: &[u8]) -> usize {
+    rom.len().div_ceil(ROM_BANK_SIZE).max(2).next_power_of_two()
+}
+
+/// Reads `offset` within the given ROM bank. Open bus reads as 0xFF if the ROM file is shorter than
+/// the bank count implies.
+pub fn read_rom_bank(rom: &[u8], bank: usize, offset: u16) -> u8 {
+    let index = bank * ROM_BANK_SIZE + (offset as usize & (ROM_BANK_SIZE - 1));
+    rom.get(index).copied().unwrap_or(0xFF)
+}
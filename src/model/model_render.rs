@@ -5,11 +5,39 @@ pub struct OamScanData {
     pub objects: VecDeque<u16>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FetcherStep {
+    GetTile,
+    GetTileDataLow,
+    GetTileDataHigh,
+    Push,
+}
+
 pub struct PixelRenderData {
     pub background_queue: VecDeque<u8>,
-    pub obj_queue: VecDeque<u8>,
+    pub obj_queue: VecDeque<u16>,
     pub x: u8,
+    /// Tile the fetcher is on, counted from the left edge of the background or window
     pub tile_x: u8,
+    /// The window has started on this line and replaced the background
+    pub fetching_window: bool,
+    /// Pixels dropped from the front of the background queue instead of being drawn
+    pub pixels_to_discard: u8,
+    pub fetcher_step: FetcherStep,
+    /// Dots spent on the current fetcher step
+    pub fetcher_dots: u8,
+    /// Dots before the fetcher starts working on the line
+    pub fetcher_delay: u8,
+    pub fetched_tile_index: u8,
+    /// CGB background map attributes of the fetched tile, always 0 on the DMG
+    pub fetched_attributes: u8,
+    pub fetched_row: u8,
+    pub fetched_tile_low: u8,
+    pub fetched_tile_high: u8,
+    /// Dots left before the pixel pipeline resumes after fetching objects
+    pub obj_stall_dots: u8,
+    /// Background or window tile that has already delayed an object fetch on this line
+    pub last_obj_tile: Option<i16>,
 }
 
 impl PixelRenderData {
@@ -19,6 +47,18 @@ impl PixelRenderData {
             obj_queue: VecDeque::new(),
             x: 0,
             tile_x: 0,
+            fetching_window: false,
+            pixels_to_discard: 0,
+            fetcher_step: FetcherStep::GetTile,
+            fetcher_dots: 0,
+            fetcher_delay: 0,
+            fetched_tile_index: 0,
+            fetched_attributes: 0,
+            fetched_row: 0,
+            fetched_tile_low: 0,
+            fetched_tile_high: 0,
+            obj_stall_dots: 0,
+            last_obj_tile: None,
         }
     }
 
@@ -27,6 +67,24 @@ impl PixelRenderData {
         self.obj_queue.clear();
         self.x = 0;
         self.tile_x = 0;
+        self.fetching_window = false;
+        self.pixels_to_discard = 0;
+        self.fetcher_step = FetcherStep::GetTile;
+        self.fetcher_dots = 0;
+        self.obj_stall_dots = 0;
+        self.last_obj_tile = None;
+        // The first tile fetched on every line is thrown away, delaying the first pixel
+        self.fetcher_delay = 6;
+    }
+
+    /// Switches the fetcher from the background to the window for the rest of the line
+    pub fn start_window(&mut self, pixels_to_discard: u8) {
+        self.background_queue.clear();
+        self.fetching_window = true;
+        self.tile_x = 0;
+        self.pixels_to_discard = pixels_to_discard;
+        self.fetcher_step = FetcherStep::GetTile;
+        self.fetcher_dots = 0;
     }
 }
 
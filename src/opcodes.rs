@@ -1,22 +1,24 @@
 use bitmatch::bitmatch;
 
+use crate::constants::{ADDRESS_DIV, ADDRESS_IE, ADDRESS_IF, ADDRESS_KEY1};
 use crate::debug::flags::{DEBUG_PRINT_WHEN_PC, DEBUG_PRINT_WHEN_PC_TIMES, DEBUG_TRACK_JUMPS};
 use crate::debug::metrics::DebugMetrics;
 use crate::memory::{MemoryController, RegisterFlags};
 use crate::operations::*;
 
-fn get_register_mut_by_code(mem: &mut dyn MemoryController, code: u8) -> &mut u8 {
+fn set_register_by_code(mem: &mut dyn MemoryController, code: u8, val: u8) {
     match code {
-        0b00000111 => &mut mem.r().a,
-        0 => &mut mem.r().bc.ind.0,
-        0b00000001 => &mut mem.r().bc.ind.1,
-        0b00000010 => &mut mem.r().de.ind.0,
-        0b00000011 => &mut mem.r().de.ind.1,
-        0b00000100 => &mut mem.r().bc.ind.0,
-        0b00000101 => &mut mem.r().bc.ind.1,
-        0b00000110 => mem.mut_8(mem.r_i().hl.r16()),
+        0b00000111 => mem.r().a = val,
+        0 => mem.r().bc.ind.0 = val,
+        0b00000001 => mem.r().bc.ind.1 = val,
+        0b00000010 => mem.r().de.ind.0 = val,
+        0b00000011 => mem.r().de.ind.1 = val,
+        0b00000100 => mem.r().hl.ind.0 = val,
+        0b00000101 => mem.r().hl.ind.1 = val,
+        // Goes through write_8 so (HL) reaches MBC registers, IO side effects and access locks
+        0b00000110 => mem.write_8(mem.r_i().hl.r16(), val),
         _ => panic!(
-            "Unrecognized register code in get_register_mut: {:#b} (shifted to lsb?)",
+            "Unrecognized register code in set_register: {:#b} (shifted to lsb?)",
             code
         ),
     }
@@ -134,7 +136,11 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
         }
     }
 
-    mem.r().pc += 1;
+    if mem.shared_data().halt_bug {
+        mem.shared_data_mut().halt_bug = false;
+    } else {
+        mem.r().pc += 1;
+    }
 
     /*
        https://users.rust-lang.org/t/why-is-a-lookup-table-faster-than-a-match-expression/24233
@@ -248,7 +254,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
         "00_lll_100" => {
             // INC r, INC (HL)
             let val = inc_8(get_register_val_code(mem, l), mem);
-            *get_register_mut_by_code(mem, l) = val;
+            set_register_by_code(mem, l, val);
             if l == 0b00000110 {
                 cycles += 2;
             }
@@ -256,14 +262,15 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
         "00_lll_101" => {
             // DEC r, DEC (HL)
             let val = dec_8(get_register_val_code(mem, l), mem);
-            *get_register_mut_by_code(mem, l) = val;
+            set_register_by_code(mem, l, val);
             if l == 0b00000110 {
                 cycles += 2;
             }
         }
         "00_mmm_110" => {
             // LD r n
-            *get_register_mut_by_code(mem, m) = mem.read_8(mem.r_i().pc);
+            let val = mem.read_8(mem.r_i().pc);
+            set_register_by_code(mem, m, val);
             mem.r().pc += 1;
             cycles += 1;
             if m == 0b00000110 {
@@ -353,7 +360,17 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
         "00_010_000" => {
             // STOP
             // see page 23
-            todo!("Need to figure out interrupts first before STOP");
+            // The second byte of STOP is ignored
+            mem.r().pc += 1;
+            mem.write_8(ADDRESS_DIV, 0);
+            if mem.shared_data().cgb_mode && (mem.read_8_sys(ADDRESS_KEY1) & 1) != 0 {
+                // A speed switch was armed through KEY1, STOP switches speed instead of stopping
+                let double_speed = !mem.shared_data().double_speed;
+                mem.shared_data_mut().double_speed = double_speed;
+                mem.write_8_sys(ADDRESS_KEY1, ((double_speed as u8) << 7) | 0x7E);
+            } else {
+                mem.shared_data_mut().stopped = true;
+            }
         }
         "00_110_111" => {
             // SCF
@@ -371,13 +388,17 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
         "01_110_110" => {
             // HALT
             // see page 23
-            todo!("Need to figure out interrupts first before HALT");
+            let interrupt_pending = (mem.read_8(ADDRESS_IE) & mem.read_8(ADDRESS_IF) & 0x1F) != 0;
+            if interrupt_pending && !*mem.ime() {
+                mem.shared_data_mut().halt_bug = true;
+            } else {
+                mem.shared_data_mut().halted = true;
+            }
         }
         "01_mmm_lll" => {
             // LD r r'
             let from_val = get_register_val_code(mem, l);
-            let to = get_register_mut_by_code(mem, m);
-            *to = from_val;
+            set_register_by_code(mem, m, from_val);
             if m == 0b00000110 || l == 0b00000110 {
                 cycles += 1;
             }
@@ -497,7 +518,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_000_rrr" => {
                     // RLC r, RLC (HL)
                     let result = rlc(get_register_val_code(mem, r), mem, false);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -506,7 +527,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_001_rrr" => {
                     // RRC r, RRC (HL)
                     let result = rrc(get_register_val_code(mem, r), mem, false);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -515,7 +536,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_010_rrr" => {
                     // RL r, RL (HL)
                     let result = rl(get_register_val_code(mem, r), mem, false);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -524,7 +545,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_011_rrr" => {
                     // RR r, RR (HL)
                     let result = rr(get_register_val_code(mem, r), mem, false);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -533,7 +554,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_100_rrr" => {
                     // SLA r, SLA (HL)
                     let result = sla(get_register_val_code(mem, r), mem);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -542,7 +563,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_101_rrr" => {
                     // SRA r, SRA (HL)
                     let result = sra(get_register_val_code(mem, r), mem);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -551,7 +572,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_110_rrr" => {
                     // SWAP r, SWAP (HL)
                     let result = swap(get_register_val_code(mem, r), mem);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -560,7 +581,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "00_111_rrr" => {
                     // SRL r, SRL (HL)
                     let result = srl(get_register_val_code(mem, r), mem);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 2;
@@ -577,7 +598,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "10_bbb_rrr" => {
                     // RES b, r, RES b, (HL)
                     let result = res(get_register_val_code(mem, r), b);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 1;
@@ -586,7 +607,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
                 "11_bbb_rrr" => {
                     // SET b, r, SET b, (HL)
                     let result = set(get_register_val_code(mem, r), b);
-                    *get_register_mut_by_code(mem, r) = result;
+                    set_register_by_code(mem, r, result);
 
                     if r == 0b00000110 {
                         cycles += 1;
@@ -911,7 +932,7 @@ pub fn process_instruction(mem: &mut dyn MemoryController, metrics: &mut DebugMe
 
 #[cfg(test)]
 mod tests {
-    use crate::{debug::metrics::DebugMetrics, memory::MemoryController, memory_controllers::basic_memory::BasicMemory};
+    use crate::{constants::{ADDRESS_IE, ADDRESS_IF, ADDRESS_KEY1}, debug::metrics::DebugMetrics, memory::MemoryController, memory_controllers::basic_memory::BasicMemory};
 
     use super::process_instruction;
 
@@ -969,4 +990,74 @@ mod tests {
         assert_eq!(m.r().hl.ind.0, 0x3A);
         assert_eq!(m.r().hl.ind.1, 0x5B);
     }
+
+    #[test]
+    fn halt_without_pending_interrupt_halts() {
+        let mut m = BasicMemory::default();
+        let mut metrics = DebugMetrics::new();
+        m.r().pc = 0x8000;
+        m.write_8(0x8000, 0b01_110_110);
+        process_instruction(&mut m, &mut metrics);
+
+        assert!(m.shared_data().halted);
+        assert!(!m.shared_data().halt_bug);
+        assert_eq!(0x8001, m.r().pc);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        let mut m = BasicMemory::default();
+        let mut metrics = DebugMetrics::new();
+        m.write_8(ADDRESS_IE, 1);
+        m.write_8(ADDRESS_IF, 1);
+        *m.ime() = false;
+
+        m.r().pc = 0x8000;
+        m.write_8(0x8000, 0b01_110_110);
+        // INC A
+        m.write_8(0x8001, 0b00_111_100);
+        process_instruction(&mut m, &mut metrics);
+        assert!(!m.shared_data().halted);
+
+        process_instruction(&mut m, &mut metrics);
+        assert_eq!(0x8001, m.r().pc);
+        process_instruction(&mut m, &mut metrics);
+        assert_eq!(0x8002, m.r().pc);
+        assert_eq!(2, m.r().a);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut m = BasicMemory::default();
+        let mut metrics = DebugMetrics::new();
+        m.shared_data_mut().cgb_mode = true;
+        m.write_8(ADDRESS_KEY1, 1);
+
+        m.r().pc = 0x8000;
+        m.write_8(0x8000, 0b00_010_000);
+        process_instruction(&mut m, &mut metrics);
+
+        assert!(m.shared_data().double_speed);
+        assert!(!m.shared_data().stopped);
+        assert_eq!(0xFE, m.read_8(ADDRESS_KEY1));
+    }
+
+    #[test]
+    fn load_immediate_into_h_and_l() {
+        let mut m = BasicMemory::default();
+        let mut metrics = DebugMetrics::new();
+
+        m.r().pc = 0x8000;
+        // LD H,0x12
+        m.write_8(0x8000, 0b00_100_110);
+        m.write_8(0x8001, 0x12);
+        // LD L,0x34
+        m.write_8(0x8002, 0b00_101_110);
+        m.write_8(0x8003, 0x34);
+        process_instruction(&mut m, &mut metrics);
+        process_instruction(&mut m, &mut metrics);
+
+        assert_eq!(0x1234, m.r().hl.r16());
+        assert_eq!(0, m.r().bc.r16());
+    }
 }
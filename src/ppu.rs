@@ -0,0 +1,817 @@
+use std::collections::VecDeque;
+
+use morton_encoding::morton_encode;
+
+use crate::{
+    constants::*,
+    lcd::Lcd,
+    memory::MemoryController,
+    model::model_render::{FetcherStep, OamScanData, PixelRenderData, PpuData},
+};
+
+/// Flags stored above the colour in background FIFO pixels. The CGB palette is in bits 2-4.
+const BG_PIXEL_PRIORITY: u8 = 1 << 5;
+const BG_PIXEL_CGB_PALETTE_SHIFT: u8 = 2;
+
+/// Flags stored above the colour in object FIFO pixels
+const OBJ_PIXEL_BG_PRIORITY: u16 = 1 << 2;
+const OBJ_PIXEL_PALETTE_1: u16 = 1 << 3;
+const OBJ_PIXEL_CGB_PALETTE_SHIFT: u16 = 4;
+/// The object's position in OAM, which decides which object is on top in CGB mode
+const OBJ_PIXEL_OAM_INDEX_SHIFT: u16 = 8;
+
+/// Dots into line 153 before LY changes to 0
+const LY_153_DOTS: i32 = 4;
+
+pub struct Ppu {
+    dots_left: i32,
+    oam_scan: OamScanData,
+    pixel_render: PixelRenderData,
+    first_dot_after_switch: bool,
+    last_stat_interrupt_state: bool,
+    ppu_data: VecDeque<PpuData>,
+    /// Set once LY has matched WY this frame. The window can't show before then.
+    wy_triggered: bool,
+    /// Internal line counter for the window. Only advances on lines where the window was drawn,
+    /// so hiding the window for some lines doesn't skip part of it.
+    window_line: u8,
+    /// LY has already wrapped to 0 on line 153
+    last_line_of_frame: bool,
+    lcd_enabled: bool,
+    /// Dots since the last frame boundary while the LCD is off
+    lcd_off_dots: u64,
+    /// Line 0 right after the LCD is turned on skips OAM scan
+    first_line_after_enable: bool,
+    /// The first frame after the LCD is turned on isn't shown
+    hide_frame: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            dots_left: 1,
+            oam_scan: OamScanData {
+                current_object: 0,
+                objects: VecDeque::new(),
+            },
+            pixel_render: PixelRenderData::new(),
+            first_dot_after_switch: false,
+            last_stat_interrupt_state: false,
+            ppu_data: VecDeque::new(),
+            wy_triggered: false,
+            window_line: 0,
+            last_line_of_frame: false,
+            lcd_enabled: false,
+            lcd_off_dots: 0,
+            first_line_after_enable: false,
+            hide_frame: false,
+        }
+    }
+
+    /// The PPU does nothing while the LCD is off, but frames still end on time so the rest of
+    /// the system keeps running at the right speed
+    fn step_lcd_off(&mut self, mem: &mut dyn MemoryController, lcd: &mut Lcd) -> bool {
+        if self.lcd_enabled {
+            self.lcd_enabled = false;
+            self.lcd_off_dots = 0;
+            mem.write_8_sys(ADDRESS_LY, 0);
+            let stat = mem.read_8_sys(ADDRESS_STAT);
+            mem.write_8_sys(ADDRESS_STAT, stat & !0b00000011);
+            lcd.clear();
+        }
+
+        self.lcd_off_dots += 1;
+        if self.lcd_off_dots == T_CYCLES_PER_FRAME {
+            self.lcd_off_dots = 0;
+            return true;
+        }
+        false
+    }
+
+    /// Restarts the PPU from the start of line 0 when the LCD is turned on
+    fn enable_lcd(&mut self, mem: &mut dyn MemoryController) {
+        self.lcd_enabled = true;
+        self.first_line_after_enable = true;
+        self.hide_frame = true;
+        // Line 0 stays in horizontal blank for the time OAM scan would take. + 1 since the dot
+        // being stepped now counts.
+        self.dots_left = 80 + 1;
+        self.first_dot_after_switch = true;
+        self.last_stat_interrupt_state = false;
+        self.last_line_of_frame = false;
+        self.wy_triggered = false;
+        self.window_line = 0;
+        self.oam_scan.objects.clear();
+
+        mem.write_8_sys(ADDRESS_LY, 0);
+        let stat = mem.read_8_sys(ADDRESS_STAT);
+        mem.write_8_sys(ADDRESS_STAT, stat & !0b00000011);
+    }
+
+    /// Runs the fetcher and pushes at most one pixel to the LCD
+    fn step_pixel_pipeline(&mut self, mem: &dyn MemoryController, lcd: &mut Lcd, lcdc: u8, ly: u8) {
+        let window_enabled = (lcdc & LCDC_WINDOW_ENABLE) != 0;
+        if window_enabled && self.wy_triggered && !self.pixel_render.fetching_window {
+            // WX is the left edge of the window plus 7. Below 7 the window starts at
+            // the left edge of the screen with its first 7 - WX pixels cut off.
+            let wx = mem.read_8_sys(ADDRESS_WX);
+            if self.pixel_render.x as u16 + 7 >= wx as u16 {
+                self.pixel_render.start_window(7u8.saturating_sub(wx));
+            }
+        }
+
+        self.step_fetcher(mem, lcdc, ly);
+
+        // https://gbdev.io/pandocs/pixel_fifo.html
+        if !self.pixel_render.background_queue.is_empty() {
+            if self.pixel_render.pixels_to_discard > 0 {
+                self.pixel_render.background_queue.pop_front();
+                self.pixel_render.pixels_to_discard -= 1;
+            } else {
+                let stall_dots = self.fetch_objects(mem, lcdc, ly);
+                if stall_dots > 0 {
+                    // The pixel is drawn once the objects have been fetched
+                    self.pixel_render.obj_stall_dots = stall_dots - 1;
+                } else {
+                    // actually draw a pixel now
+                    let bg = self.pixel_render.background_queue.pop_front();
+                    let obj = self.pixel_render.obj_queue.pop_front();
+                    if mem.shared_data().cgb_mode {
+                        let bg = bg.unwrap_or(0);
+                        let color = match obj {
+                            Some(objv) if cgb_obj_pixel_visible(lcdc, bg, objv) => mem.internal().palette_color(
+                                true,
+                                ((objv >> OBJ_PIXEL_CGB_PALETTE_SHIFT) & 7) as u8,
+                                (objv & 3) as u8,
+                            ),
+                            _ => mem.internal().palette_color(false, (bg >> BG_PIXEL_CGB_PALETTE_SHIFT) & 7, bg & 3),
+                        };
+                        lcd.draw_pixel_rgb555(self.pixel_render.x, ly, color);
+                        self.pixel_render.x += 1;
+                        return;
+                    }
+
+                    // Clearing LCDC bit 0 blanks both the background and the window
+                    let bg_disabled = lcdc & LCDC_BG_WINDOW_ENABLE == 0;
+                    let bg_color = match bg {
+                        Some(bgv) if !bg_disabled => bgv,
+                        _ => 0,
+                    };
+                    let shade = match obj {
+                        // Objects behind the background only show over background colour 0
+                        Some(objv) if objv & 3 != 0 && (objv & OBJ_PIXEL_BG_PRIORITY == 0 || bg_color == 0) => {
+                            let palette_address = if objv & OBJ_PIXEL_PALETTE_1 != 0 {
+                                ADDRESS_OBP1
+                            } else {
+                                ADDRESS_OBP0
+                            };
+                            apply_palette(mem.read_8_sys(palette_address), (objv & 3) as u8)
+                        }
+                        // A disabled background is always the lightest shade, not BGP colour 0
+                        _ if bg_disabled => 0,
+                        _ => apply_palette(mem.read_8_sys(ADDRESS_BGP), bg_color),
+                    };
+                    lcd.draw_pixel(self.pixel_render.x, ly, shade);
+                    self.pixel_render.x += 1;
+                }
+            }
+        }
+    }
+
+    /// Merges every object starting at the current pixel into the object queue. Returns the
+    /// dots the pixel pipeline stalls while they are fetched.
+    fn fetch_objects(&mut self, mem: &dyn MemoryController, lcdc: u8, ly: u8) -> u8 {
+        let mut stall_dots = 0;
+        if (lcdc & LCDC_OBJ_ENABLE) == 0 {
+            return stall_dots;
+        }
+
+        while let Some(&obj_addr) = self.oam_scan.objects.front() {
+            // Object X is the right edge plus 8 so objects can be partly off the left
+            // side of the screen
+            let obj_x = mem.read_8_sys(obj_addr + 1);
+            if obj_x > self.pixel_render.x + 8 {
+                break;
+            }
+            self.oam_scan.objects.pop_front();
+
+            // Only the first object over each background tile waits for that tile's fetch
+            let (tile, tile_pixel) = self.obj_background_position(mem, obj_x);
+            let tile_pixel = if self.pixel_render.last_obj_tile == Some(tile) {
+                None
+            } else {
+                self.pixel_render.last_obj_tile = Some(tile);
+                Some(tile_pixel)
+            };
+            stall_dots += obj_fetch_penalty(obj_x, tile_pixel);
+
+            let cgb_mode = mem.shared_data().cgb_mode;
+            let tall_tiles = (lcdc & LCDC_OBJ_SIZE) != 0;
+            let obj_height = if tall_tiles { 16 } else { 8 };
+            let obj_y = mem.read_8_sys(obj_addr);
+            let obj_index = mem.read_8_sys(obj_addr + 2);
+            let obj_attrs = mem.read_8_sys(obj_addr + 3);
+
+            let row = obj_row(ly, obj_y, obj_height, (obj_attrs & OBJ_ATTR_Y_FLIP) != 0);
+            let tile_data_address = obj_row_address(obj_index, row, tall_tiles);
+            let bank = if cgb_mode && (obj_attrs & OBJ_ATTR_BANK) != 0 { 1 } else { 0 };
+            let tile_low = mem.internal().read_vram(bank, tile_data_address);
+            let tile_high = mem.internal().read_vram(bank, tile_data_address + 1);
+            let pixels = tile_row_pixels(tile_low, tile_high, (obj_attrs & OBJ_ATTR_X_FLIP) != 0);
+
+            let mut flags = ((obj_addr - ADDRESS_OAM_START) / 4) << OBJ_PIXEL_OAM_INDEX_SHIFT;
+            if (obj_attrs & OBJ_ATTR_PRIORITY) != 0 {
+                flags |= OBJ_PIXEL_BG_PRIORITY;
+            }
+            if (obj_attrs & OBJ_ATTR_PALETTE) != 0 {
+                flags |= OBJ_PIXEL_PALETTE_1;
+            }
+            if cgb_mode {
+                flags |= ((obj_attrs & OBJ_ATTR_CGB_PALETTE) as u16) << OBJ_PIXEL_CGB_PALETTE_SHIFT;
+            }
+
+            // Pixels left of the current position are never drawn
+            let already_passed = (self.pixel_render.x + 8 - obj_x) as usize;
+            for (i, pixel) in pixels.into_iter().skip(already_passed).enumerate() {
+                let pixel = pixel as u16 | flags;
+                if i < self.pixel_render.obj_queue.len() {
+                    // Objects fetched earlier win unless their pixel is transparent. In CGB mode
+                    // the object earlier in OAM wins instead.
+                    let queued = self.pixel_render.obj_queue[i];
+                    let replace = queued & 3 == 0
+                        || (cgb_mode
+                            && pixel & 3 != 0
+                            && pixel >> OBJ_PIXEL_OAM_INDEX_SHIFT < queued >> OBJ_PIXEL_OAM_INDEX_SHIFT);
+                    if replace {
+                        self.pixel_render.obj_queue[i] = pixel;
+                    }
+                } else {
+                    self.pixel_render.obj_queue.push_back(pixel);
+                }
+            }
+        }
+
+        stall_dots
+    }
+
+    /// Background or window tile under the leftmost pixel of an object, and the pixel's position
+    /// within that tile
+    fn obj_background_position(&self, mem: &dyn MemoryController, obj_x: u8) -> (i16, u8) {
+        let position = if self.pixel_render.fetching_window {
+            obj_x as i16 - 1 - mem.read_8_sys(ADDRESS_WX) as i16
+        } else {
+            obj_x as i16 - 8 + mem.read_8_sys(ADDRESS_SCX) as i16
+        };
+        (position.div_euclid(8), position.rem_euclid(8) as u8)
+    }
+
+    /// Advances the background and window fetcher by one dot. Every step except pushing takes
+    /// 2 dots.
+    fn step_fetcher(&mut self, mem: &dyn MemoryController, lcdc: u8, ly: u8) {
+        let render = &mut self.pixel_render;
+        if render.fetcher_delay > 0 {
+            render.fetcher_delay -= 1;
+            return;
+        }
+
+        if render.fetcher_step != FetcherStep::Push {
+            render.fetcher_dots += 1;
+            if render.fetcher_dots < 2 {
+                return;
+            }
+            render.fetcher_dots = 0;
+        }
+
+        match render.fetcher_step {
+            FetcherStep::GetTile => {
+                let (tilemap_address, map_x, map_y) = if render.fetching_window {
+                    let tilemap_address = if (lcdc & LCDC_WINDOW_TILEMAP) != 0 {
+                        ADDRESS_TILEMAP_2
+                    } else {
+                        ADDRESS_TILEMAP_1
+                    };
+                    (tilemap_address, render.tile_x & 31, self.window_line)
+                } else {
+                    let tilemap_address = if (lcdc & LCDC_BG_TILEMAP) != 0 {
+                        ADDRESS_TILEMAP_2
+                    } else {
+                        ADDRESS_TILEMAP_1
+                    };
+                    let scx = mem.read_8_sys(ADDRESS_SCX);
+                    let scy = mem.read_8_sys(ADDRESS_SCY);
+                    // The background map is 256x256 pixels and wraps around in both directions
+                    (tilemap_address, (scx / 8 + render.tile_x) & 31, ly.wrapping_add(scy))
+                };
+
+                let map_address = tilemap_address + map_x as u16 + (map_y / 8) as u16 * 32;
+                render.fetched_tile_index = mem.internal().read_vram(0, map_address);
+                render.fetched_attributes = if mem.shared_data().cgb_mode {
+                    mem.internal().read_vram(1, map_address)
+                } else {
+                    0
+                };
+                render.fetched_row = if (render.fetched_attributes & BG_ATTR_Y_FLIP) != 0 {
+                    7 - map_y % 8
+                } else {
+                    map_y % 8
+                };
+                render.fetcher_step = FetcherStep::GetTileDataLow;
+            }
+            FetcherStep::GetTileDataLow => {
+                let address = bg_tile_data_address(lcdc, render.fetched_tile_index)
+                    + render.fetched_row as u16 * 2;
+                let bank = (render.fetched_attributes & BG_ATTR_BANK != 0) as u8;
+                render.fetched_tile_low = mem.internal().read_vram(bank, address);
+                render.fetcher_step = FetcherStep::GetTileDataHigh;
+            }
+            FetcherStep::GetTileDataHigh => {
+                let address = bg_tile_data_address(lcdc, render.fetched_tile_index)
+                    + render.fetched_row as u16 * 2;
+                let bank = (render.fetched_attributes & BG_ATTR_BANK != 0) as u8;
+                render.fetched_tile_high = mem.internal().read_vram(bank, address + 1);
+                render.fetcher_step = FetcherStep::Push;
+            }
+            FetcherStep::Push => {
+                // The row is only pushed once the FIFO is empty, until then the fetcher waits
+                if render.background_queue.is_empty() {
+                    let attributes = render.fetched_attributes;
+                    let mut flags = (attributes & BG_ATTR_PALETTE) << BG_PIXEL_CGB_PALETTE_SHIFT;
+                    if (attributes & BG_ATTR_PRIORITY) != 0 {
+                        flags |= BG_PIXEL_PRIORITY;
+                    }
+                    let pixels = tile_row_pixels(
+                        render.fetched_tile_low,
+                        render.fetched_tile_high,
+                        (attributes & BG_ATTR_X_FLIP) != 0,
+                    );
+                    render.background_queue.extend(pixels.map(|pixel| pixel | flags));
+                    render.tile_x += 1;
+                    render.fetcher_step = FetcherStep::GetTile;
+                }
+            }
+        }
+    }
+
+    /// Runs the PPU for a single dot. Returns true on the dot where a finished frame should be shown.
+    pub fn step(&mut self, mem: &mut dyn MemoryController, lcd: &mut Lcd) -> bool {
+        if mem.read_8_sys(ADDRESS_LCDC) & LCDC_LCD_ENABLE == 0 {
+            return self.step_lcd_off(mem, lcd);
+        }
+        if !self.lcd_enabled {
+            self.enable_lcd(mem);
+        }
+
+        let mut frame_ready = false;
+        self.dots_left -= 1;
+        let reset_first_dot_flag = self.first_dot_after_switch;
+
+        let mut stat = mem.read_8_sys(ADDRESS_STAT);
+        let mut ppu_mode = stat & 0b00000011;
+
+        if crate::debug::flags::DEBUG_PRINT_PPU {
+            println!("dots_left: {}", self.dots_left);
+            println!("ppu_mode: {}", ppu_mode);
+        }
+
+        let ly = mem.read_8_sys(ADDRESS_LY);
+        if self.ppu_data.len() >= 600 {
+            self.ppu_data.pop_front();
+        }
+        self.ppu_data.push_back(PpuData { mode: ppu_mode, dots_left: self.dots_left, ly });
+
+        match ppu_mode {
+            PPU_MODE_OAM_SCAN => {
+                if self.first_dot_after_switch {
+                    self.oam_scan.current_object = 0;
+                    self.oam_scan.objects.clear();
+
+                    // WY is compared at the start of every line so mid-frame changes take effect
+                    if ly == mem.read_8_sys(ADDRESS_WY) {
+                        self.wy_triggered = true;
+                    }
+                }
+
+                if self.dots_left % 2 == 0 && self.oam_scan.objects.len() < 10 {
+                    let lcdc = mem.read_8_sys(ADDRESS_LCDC);
+                    let obj_height: u8 = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+                    let ly = mem.read_8_sys(ADDRESS_LY);
+
+                    let obj_addr = ADDRESS_OAM_START + 4 * self.oam_scan.current_object;
+                    let obj_y = mem.read_8_sys(obj_addr);
+
+                    if obj_on_screen(ly, obj_y, obj_height) {
+                        self.oam_scan.objects.push_back(obj_addr);
+                    }
+
+                    self.oam_scan.current_object += 1;
+                }
+
+                if self.dots_left == 0 {
+                    // Mode 3 and horizontal blank share the rest of the line. Mode 3 ends once
+                    // every pixel has been pushed, so its length varies.
+                    self.dots_left = 456 - 80;
+                    // + 1 will change mode from 2 to 3
+                    mem.write_8_sys(ADDRESS_STAT, stat + 1);
+                    self.first_dot_after_switch = true;
+                }
+            }
+            PPU_MODE_RENDER_PIXEL => {
+                if self.first_dot_after_switch {
+                    self.pixel_render.reset();
+                    // Only the fine scroll is latched for the line, the coarse scroll is read on
+                    // every tile fetch
+                    self.pixel_render.pixels_to_discard = mem.read_8_sys(ADDRESS_SCX) & 7;
+
+                    // Objects are fetched left to right, ties go to the one earlier in OAM
+                    self.oam_scan
+                        .objects
+                        .make_contiguous()
+                        .sort_by_key(|obj_addr| mem.read_8_sys(obj_addr + 1));
+                }
+
+                let lcdc = mem.read_8_sys(ADDRESS_LCDC);
+
+                if ly >= 144 {
+                    println!("ly is {} in PPU_MODE_RENDER_PIXEL. PPU data: {:?}", ly, self.ppu_data);
+                    panic!("ly is {} in PPU_MODE_RENDER_PIXEL", ly)
+                }
+
+                if self.pixel_render.obj_stall_dots > 0 {
+                    // The background fetcher and the pixel output are paused while objects are
+                    // fetched
+                    self.pixel_render.obj_stall_dots -= 1;
+                } else {
+                    self.step_pixel_pipeline(mem, lcd, lcdc, ly);
+                }
+
+                if self.pixel_render.x == 160 {
+                    if self.pixel_render.fetching_window {
+                        self.window_line += 1;
+                    }
+
+                    // transition to horiz blank, which lasts for the rest of the line
+                    // - 3 will change mode from 3 to 0
+                    mem.write_8_sys(ADDRESS_STAT, stat - 3);
+                    mem.shared_data_mut().vram_dma.hblank_started = true;
+                    self.first_dot_after_switch = true;
+                }
+            }
+            PPU_MODE_HORIZ_BLANK => {
+                if self.dots_left == 0 && self.first_line_after_enable {
+                    self.first_line_after_enable = false;
+                    if ly == mem.read_8_sys(ADDRESS_WY) {
+                        self.wy_triggered = true;
+                    }
+
+                    // transition straight to drawing, there are no objects on this line
+                    self.dots_left = 456 - 80;
+                    // + 3 will change mode from 0 to 3
+                    mem.write_8_sys(ADDRESS_STAT, stat + 3);
+                    self.first_dot_after_switch = true;
+                } else if self.dots_left == 0 {
+                    let ly = mem.read_8_sys(ADDRESS_LY);
+                    if ly == 143 {
+                        // transition to vertical blank
+                        self.dots_left = 456;
+                        // + 1 will change mode from 0 to 1
+                        mem.write_8_sys(ADDRESS_STAT, stat + 1);
+                        mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 1);
+                    } else {
+                        // transition to OAM scan
+                        self.dots_left = 80;
+                        // + 2 will change mode from 0 to 2
+                        mem.write_8_sys(ADDRESS_STAT, stat + 2);
+                    }
+
+                    mem.write_8_sys(ADDRESS_LY, ly + 1);
+                    self.first_dot_after_switch = true;
+                }
+            }
+            PPU_MODE_VERT_BLANK => {
+                if self.first_dot_after_switch {
+                    if self.hide_frame {
+                        // The LCD only starts showing frames once one has been fully drawn
+                        self.hide_frame = false;
+                        lcd.clear();
+                    }
+                    frame_ready = true;
+                }
+
+                // LY only reads 153 for the first few dots of the last line and 0 for the rest
+                // of it, so LYC=0 matches before the frame starts
+                if ly == 153 && self.dots_left == 456 - LY_153_DOTS {
+                    mem.write_8_sys(ADDRESS_LY, 0);
+                    self.last_line_of_frame = true;
+                }
+
+                if self.dots_left == 0 {
+                    if self.last_line_of_frame {
+                        // transition to OAM scan
+                        self.dots_left = 80;
+                        // + 1 will change mode from 1 to 2
+                        mem.write_8_sys(ADDRESS_STAT, stat + 1);
+                        self.first_dot_after_switch = true;
+                        self.last_line_of_frame = false;
+                        self.wy_triggered = false;
+                        self.window_line = 0;
+                    } else {
+                        self.dots_left = 456;
+                        mem.write_8_sys(ADDRESS_LY, mem.read_8_sys(ADDRESS_LY) + 1);
+                    }
+                }
+            }
+            _ => panic!("Invalid ppu_mode"),
+        }
+
+        // get the latest values
+        let ly = mem.read_8_sys(ADDRESS_LY);
+        let lyc = mem.read_8_sys(ADDRESS_LYC);
+        stat = mem.read_8_sys(ADDRESS_STAT);
+        ppu_mode = stat & 0b00000011;
+        let ly_match = ly == lyc;
+        let vblank_start = ppu_mode == PPU_MODE_VERT_BLANK && ly == 144 && self.first_dot_after_switch;
+        // The sources are ORed into a single line and the interrupt is only requested when the
+        // line rises. While one source holds it high the others can't request anything, which
+        // is known as STAT blocking.
+        if stat_interrupt_line(stat, ppu_mode, ly_match, vblank_start) {
+            if !self.last_stat_interrupt_state {
+                self.last_stat_interrupt_state = true;
+                mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 2);
+            }
+        } else {
+            self.last_stat_interrupt_state = false;
+        }
+
+        if ly_match != ((stat & 1 << 2) != 0) {
+            if ly_match {
+                mem.write_8_sys(ADDRESS_STAT, stat | 1 << 2);
+            } else {
+                mem.write_8_sys(ADDRESS_STAT, stat & !(1 << 2));
+            }
+        }
+
+        if reset_first_dot_flag {
+            self.first_dot_after_switch = false;
+        }
+
+        frame_ready
+    }
+}
+
+pub fn obj_on_screen(ly: u8, obj_y: u8, obj_height: u8) -> bool {
+    // Object Y is the top edge plus 16
+    let top_above = obj_y as u16 <= ly as u16 + 16;
+    let bottom_below = obj_y as u16 + obj_height as u16 > ly as u16 + 16;
+    top_above && bottom_below
+}
+
+/// State of the line that requests the STAT interrupt. `vblank_start` is set at the start of
+/// line 144, where the mode 2 source also fires.
+pub fn stat_interrupt_line(stat: u8, ppu_mode: u8, ly_match: bool, vblank_start: bool) -> bool {
+    (ly_match && (stat & 1 << 6) != 0)
+        || ((ppu_mode == PPU_MODE_OAM_SCAN || vblank_start) && (stat & 1 << 5) != 0)
+        || (ppu_mode == PPU_MODE_VERT_BLANK && (stat & 1 << 4) != 0)
+        || (ppu_mode == PPU_MODE_HORIZ_BLANK && (stat & 1 << 3) != 0)
+}
+
+/// Dots mode 3 is extended by when an object is fetched. `tile_pixel` is the position of the
+/// object's leftmost pixel within the background or window tile under it, or None when an
+/// earlier object on that tile already paid for waiting on the background fetch.
+pub fn obj_fetch_penalty(obj_x: u8, tile_pixel: Option<u8>) -> u8 {
+    if obj_x == 0 {
+        return 11;
+    }
+
+    // The background fetch in progress has to finish first, which takes longer the further
+    // left in the tile the object starts
+    let wait_for_background = match tile_pixel {
+        Some(pixel) => 5u8.saturating_sub(pixel),
+        None => 0,
+    };
+    6 + wait_for_background
+}
+
+/// Address of a background or window tile's data
+pub fn bg_tile_data_address(lcdc: u8, tile_index: u8) -> u16 {
+    if (lcdc & LCDC_BG_AND_WINDOW_TILEDATA) != 0 {
+        ADDRESS_TILEDATA_1 + tile_index as u16 * 16
+    } else {
+        // ADDRESS_TILEDATA_2 should be 0x8800 not 0x9000 like documentation will give because
+        // this code is not using signed numbers for the index. Flipping bit 7 maps the signed
+        // index -128..=127 to 0..=255.
+        ADDRESS_TILEDATA_2 + (tile_index ^ 0x80) as u16 * 16
+    }
+}
+
+/// Whether an object pixel is drawn over a background pixel in CGB mode. The background wins
+/// over colours 1-3 when either its map attributes or the object ask for it, unless LCDC bit 0
+/// takes priority away from the background entirely.
+pub fn cgb_obj_pixel_visible(lcdc: u8, bg_pixel: u8, obj_pixel: u16) -> bool {
+    if obj_pixel & 3 == 0 {
+        return false;
+    }
+    let bg_has_priority = (bg_pixel & BG_PIXEL_PRIORITY) != 0 || (obj_pixel & OBJ_PIXEL_BG_PRIORITY) != 0;
+    (lcdc & LCDC_BG_WINDOW_ENABLE) == 0 || bg_pixel & 3 == 0 || !bg_has_priority
+}
+
+/// Maps a 2 bit colour index to a shade through BGP, OBP0 or OBP1
+pub fn apply_palette(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 3
+}
+
+/// Row of the object's tile data shown on line `ly`, counted from the top of the tile data
+pub fn obj_row(ly: u8, obj_y: u8, obj_height: u8, y_flip: bool) -> u8 {
+    let row = (ly as u16 + 16 - obj_y as u16) as u8;
+    if y_flip {
+        obj_height - 1 - row
+    } else {
+        row
+    }
+}
+
+/// Address of a row of an object's tile data. Tall objects ignore bit 0 of the tile index and
+/// use the even tile for the top half and the odd tile for the bottom half.
+pub fn obj_row_address(tile_index: u8, row: u8, tall: bool) -> u16 {
+    let tile_index = if tall { tile_index & 0xFE } else { tile_index };
+    ADDRESS_TILEDATA_1 + tile_index as u16 * 16 + row as u16 * 2
+}
+
+/// Splits a row of tile data into 2 bit colour indices, leftmost pixel first
+pub fn tile_row_pixels(tile_low: u8, tile_high: u8, x_flip: bool) -> [u8; 8] {
+    let (tile_low, tile_high) = if x_flip {
+        (tile_low.reverse_bits(), tile_high.reverse_bits())
+    } else {
+        (tile_low, tile_high)
+    };
+
+    let mut all_pixel_data = morton_encode([tile_high, tile_low]);
+    let mut pixels = [0; 8];
+    for pixel in pixels.iter_mut() {
+        *pixel = ((all_pixel_data & 0xC000) >> 14) as u8;
+        all_pixel_data <<= 2;
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::constants::*;
+
+    use super::{
+        apply_palette, bg_tile_data_address, cgb_obj_pixel_visible, obj_fetch_penalty, obj_on_screen, obj_row, obj_row_address,
+        stat_interrupt_line, tile_row_pixels, BG_PIXEL_PRIORITY, OBJ_PIXEL_BG_PRIORITY,
+    };
+
+    #[rstest]
+    #[case(0, 0, 8, false)]
+    #[case(0, 0, 16, false)]
+    #[case(0, 2, 8, false)]
+    #[case(0, 2, 16, true)]
+    #[case(0, 16, 8, true)]
+    #[case(0, 16, 16, true)]
+    #[case(143, 144, 8, false)]
+    #[case(143, 144, 16, true)]
+    #[case(143, 152, 8, true)]
+    #[case(143, 152, 16, true)]
+    #[case(143, 154, 8, true)]
+    #[case(143, 154, 16, true)]
+    #[case(143, 160, 8, false)]
+    #[case(143, 160, 16, false)]
+    fn obj_on_screen_test(
+        #[case] ly: u8,
+        #[case] obj_y: u8,
+        #[case] obj_height: u8,
+        #[case] expected_result: bool,
+    ) {
+        let result = obj_on_screen(ly, obj_y, obj_height);
+        assert_eq!(expected_result, result);
+    }
+
+    #[rstest]
+    #[case(0, 16, 8, false, 0)]
+    #[case(0, 16, 8, true, 7)]
+    #[case(7, 16, 8, false, 7)]
+    #[case(7, 16, 8, true, 0)]
+    #[case(0, 16, 16, false, 0)]
+    #[case(0, 16, 16, true, 15)]
+    #[case(10, 16, 16, false, 10)]
+    #[case(10, 16, 16, true, 5)]
+    #[case(0, 2, 16, false, 14)]
+    #[case(0, 2, 16, true, 1)]
+    #[case(100, 110, 8, false, 6)]
+    #[case(100, 110, 8, true, 1)]
+    fn obj_row_test(
+        #[case] ly: u8,
+        #[case] obj_y: u8,
+        #[case] obj_height: u8,
+        #[case] y_flip: bool,
+        #[case] expected_result: u8,
+    ) {
+        assert_eq!(expected_result, obj_row(ly, obj_y, obj_height, y_flip));
+    }
+
+    #[rstest]
+    #[case(0x10, 0, false, 0x8100)]
+    #[case(0x11, 7, false, 0x811E)]
+    #[case(0x10, 0, true, 0x8100)]
+    #[case(0x11, 0, true, 0x8100)]
+    #[case(0x11, 8, true, 0x8110)]
+    #[case(0x11, 15, true, 0x811E)]
+    #[case(0xFF, 15, true, 0x8FFE)]
+    fn obj_row_address_test(
+        #[case] tile_index: u8,
+        #[case] row: u8,
+        #[case] tall: bool,
+        #[case] expected_result: u16,
+    ) {
+        assert_eq!(expected_result, obj_row_address(tile_index, row, tall));
+    }
+
+    #[rstest]
+    #[case(0b1100_0000, 0b1010_0000, false, [3, 1, 2, 0, 0, 0, 0, 0])]
+    #[case(0b1100_0000, 0b1010_0000, true, [0, 0, 0, 0, 0, 2, 1, 3])]
+    #[case(0b0000_0001, 0b0000_0001, false, [0, 0, 0, 0, 0, 0, 0, 3])]
+    #[case(0b0000_0001, 0b0000_0001, true, [3, 0, 0, 0, 0, 0, 0, 0])]
+    fn tile_row_pixels_test(
+        #[case] tile_low: u8,
+        #[case] tile_high: u8,
+        #[case] x_flip: bool,
+        #[case] expected_result: [u8; 8],
+    ) {
+        assert_eq!(expected_result, tile_row_pixels(tile_low, tile_high, x_flip));
+    }
+
+    #[rstest]
+    #[case(0b11_10_01_00, 0, 0)]
+    #[case(0b11_10_01_00, 3, 3)]
+    #[case(0b00_01_10_11, 0, 3)]
+    #[case(0b00_01_10_11, 2, 1)]
+    #[case(0b10_11_00_01, 1, 0)]
+    fn apply_palette_test(#[case] palette: u8, #[case] color: u8, #[case] expected_result: u8) {
+        assert_eq!(expected_result, apply_palette(palette, color));
+    }
+
+    #[rstest]
+    #[case(LCDC_BG_AND_WINDOW_TILEDATA, 0, 0x8000)]
+    #[case(LCDC_BG_AND_WINDOW_TILEDATA, 0x80, 0x8800)]
+    #[case(LCDC_BG_AND_WINDOW_TILEDATA, 0xFF, 0x8FF0)]
+    #[case(0, 0, 0x9000)]
+    #[case(0, 0x7F, 0x97F0)]
+    #[case(0, 0x80, 0x8800)]
+    #[case(0, 0xFF, 0x8FF0)]
+    fn bg_tile_data_address_test(#[case] lcdc: u8, #[case] tile_index: u8, #[case] expected_result: u16) {
+        assert_eq!(expected_result, bg_tile_data_address(lcdc, tile_index));
+    }
+
+    #[rstest]
+    #[case(0, None, 11)]
+    #[case(0, Some(7), 11)]
+    #[case(8, Some(0), 11)]
+    #[case(8, Some(2), 9)]
+    #[case(8, Some(4), 7)]
+    #[case(8, Some(5), 6)]
+    #[case(8, Some(7), 6)]
+    #[case(20, None, 6)]
+    fn obj_fetch_penalty_test(#[case] obj_x: u8, #[case] tile_pixel: Option<u8>, #[case] expected_result: u8) {
+        assert_eq!(expected_result, obj_fetch_penalty(obj_x, tile_pixel));
+    }
+
+    #[rstest]
+    #[case(1 << 6, PPU_MODE_RENDER_PIXEL, true, false, true)]
+    #[case(1 << 6, PPU_MODE_RENDER_PIXEL, false, false, false)]
+    #[case(1 << 5, PPU_MODE_OAM_SCAN, false, false, true)]
+    #[case(1 << 5, PPU_MODE_VERT_BLANK, false, false, false)]
+    #[case(1 << 5, PPU_MODE_VERT_BLANK, false, true, true)]
+    #[case(1 << 4, PPU_MODE_VERT_BLANK, false, false, true)]
+    #[case(1 << 3, PPU_MODE_HORIZ_BLANK, false, false, true)]
+    #[case(1 << 3, PPU_MODE_OAM_SCAN, false, false, false)]
+    #[case(0, PPU_MODE_HORIZ_BLANK, true, true, false)]
+    fn stat_interrupt_line_test(
+        #[case] stat: u8,
+        #[case] ppu_mode: u8,
+        #[case] ly_match: bool,
+        #[case] vblank_start: bool,
+        #[case] expected_result: bool,
+    ) {
+        assert_eq!(expected_result, stat_interrupt_line(stat, ppu_mode, ly_match, vblank_start));
+    }
+
+    #[rstest]
+    #[case(0x01, 0b00, 0b01, true)]
+    #[case(0x01, 0b01, 0b00, false)]
+    #[case(0x01, 0b01, 0b01, true)]
+    #[case(0x01, 0b01 | BG_PIXEL_PRIORITY, 0b01, false)]
+    #[case(0x01, BG_PIXEL_PRIORITY, 0b01, true)]
+    #[case(0x01, 0b01, 0b01 | OBJ_PIXEL_BG_PRIORITY, false)]
+    #[case(0x00, 0b01 | BG_PIXEL_PRIORITY, 0b01 | OBJ_PIXEL_BG_PRIORITY, true)]
+    fn cgb_obj_pixel_visible_test(
+        #[case] lcdc: u8,
+        #[case] bg_pixel: u8,
+        #[case] obj_pixel: u16,
+        #[case] expected_result: bool,
+    ) {
+        assert_eq!(expected_result, cgb_obj_pixel_visible(lcdc, bg_pixel, obj_pixel));
+    }
+}
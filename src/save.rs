@@ -0,0 +1,62 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use crate::memory::MemoryController;
+
+/// Frames without a cartridge RAM write before pending changes are flushed to disk
+const FRAMES_UNTIL_FLUSH: u32 = 60;
+
+/// Keeps battery-backed cartridge RAM in a .sav file next to the ROM
+pub struct SaveFile {
+    path: PathBuf,
+    dirty: bool,
+    quiet_frames: u32,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            dirty: false,
+            quiet_frames: 0,
+        }
+    }
+
+    pub fn load(&self, mem: &mut dyn MemoryController) {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                println!("Loading save from {}", self.path.display());
+                mem.load_save_data(&data);
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => println!("Failed reading save file {}: {}", self.path.display(), err),
+        }
+
+        mem.shared_data_mut().cart_ram_written = false;
+    }
+
+    /// Call once per frame. Writes the save once the game has stopped writing to cartridge RAM
+    /// for a while so a save routine isn't flushed halfway through.
+    pub fn on_frame(&mut self, mem: &mut dyn MemoryController) {
+        if mem.shared_data().cart_ram_written {
+            mem.shared_data_mut().cart_ram_written = false;
+            self.dirty = true;
+            self.quiet_frames = 0;
+        } else if self.dirty {
+            self.quiet_frames += 1;
+            if self.quiet_frames >= FRAMES_UNTIL_FLUSH {
+                self.flush(mem);
+            }
+        }
+    }
+
+    pub fn flush(&mut self, mem: &dyn MemoryController) {
+        self.dirty = false;
+        self.quiet_frames = 0;
+
+        if let Some(data) = mem.save_data() {
+            if let Err(err) = fs::write(&self.path, data) {
+                println!("Failed writing save file {}: {}", self.path.display(), err);
+            }
+        }
+    }
+}
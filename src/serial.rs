@@ -0,0 +1,509 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::{constants::*, memory::MemoryController};
+
+/// T-cycles per bit with the 8192 Hz internal clock
+const BIT_CYCLES: u32 = 512;
+/// T-cycles per bit with the CGB's 262144 Hz fast clock
+const FAST_BIT_CYCLES: u32 = 16;
+/// How often a socket is checked for a byte clocked in by the partner
+const POLL_CYCLES: u32 = BIT_CYCLES;
+/// How long to wait for the partner to answer before treating it as not listening
+const LINK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Socket messages are a kind byte, the sequence number of the transfer and the data byte.
+/// Replies repeat the transfer's sequence number so a late one isn't taken for the next answer.
+const MESSAGE_TRANSFER: u8 = 0;
+const MESSAGE_REPLY: u8 = 1;
+
+/// The other end of the link cable
+pub trait LinkCable {
+    /// Shifts `byte` out with this side providing the clock. Returns the partner's byte, or None
+    /// if nothing answered and the line stayed high.
+    fn exchange(&mut self, byte: u8) -> Option<u8>;
+
+    /// Checks whether the partner has clocked in a byte, answering with `reply`
+    fn poll_external(&mut self, _reply: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing plugged in. Every byte received is 0xFF and an external clock never arrives.
+pub struct NoPartner;
+
+impl LinkCable for NoPartner {
+    fn exchange(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Prints every byte sent as text. Blargg's test ROMs report their results this way.
+pub struct StdoutCapture;
+
+impl LinkCable for StdoutCapture {
+    fn exchange(&mut self, byte: u8) -> Option<u8> {
+        print!("{}", byte as char);
+        let _ = io::stdout().flush();
+        None
+    }
+}
+
+enum LinkStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl LinkStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            LinkStream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            LinkStream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            LinkStream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            LinkStream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            LinkStream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            LinkStream::Unix(stream) => stream.read(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            LinkStream::Tcp(stream) => stream.write_all(buf),
+            #[cfg(unix)]
+            LinkStream::Unix(stream) => stream.write_all(buf),
+        }
+    }
+}
+
+/// Links two running emulators over a local socket. The side providing the clock sends its
+/// byte and waits for the partner's reply. The other side answers whenever it polls, so it
+/// never blocks. Once the partner misses a reply the clock side stops waiting for it until
+/// it hears from the partner again.
+pub struct SocketLink {
+    stream: Option<LinkStream>,
+    received: VecDeque<u8>,
+    /// Sequence number of the last transfer this side clocked
+    sequence: u8,
+    /// The partner answered the last transfer in time, or has sent something since
+    partner_answering: bool,
+    timeout: Duration,
+}
+
+impl SocketLink {
+    fn new(stream: LinkStream) -> Self {
+        SocketLink {
+            stream: Some(stream),
+            received: VecDeque::new(),
+            sequence: 0,
+            partner_answering: true,
+            timeout: LINK_TIMEOUT,
+        }
+    }
+
+    fn send(&mut self, kind: u8, sequence: u8, byte: u8) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_all(&[kind, sequence, byte]),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+
+    /// Reads the next message, blocking or not depending on the stream's current mode
+    fn read_message(&mut self) -> io::Result<(u8, u8, u8)> {
+        let stream = self.stream.as_mut().ok_or(ErrorKind::NotConnected)?;
+        while self.received.len() < 3 {
+            let mut buf = [0; 3];
+            let read = stream.read(&mut buf)?;
+            if read == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            self.received.extend(&buf[..read]);
+        }
+        self.partner_answering = true;
+        let mut next = || self.received.pop_front().unwrap();
+        Ok((next(), next(), next()))
+    }
+
+    /// Handles one message that isn't the reply being waited for
+    fn handle_other_message(&mut self, kind: u8, sequence: u8) -> io::Result<()> {
+        if kind == MESSAGE_TRANSFER {
+            // Both sides are providing the clock so neither is listening to the other
+            self.send(MESSAGE_REPLY, sequence, 0xFF)
+        } else {
+            // A reply to a transfer that already timed out
+            Ok(())
+        }
+    }
+
+    /// Reads messages until the reply to the current transfer arrives or there's no more data
+    fn wait_for_reply(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            match self.read_message() {
+                Ok((MESSAGE_REPLY, sequence, reply)) if sequence == self.sequence => return Ok(Some(reply)),
+                Ok((kind, sequence, _)) => self.handle_other_message(kind, sequence)?,
+                Err(err) if no_data(&err) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn disconnect(&mut self, err: io::Error) {
+        println!("Link cable disconnected: {}", err);
+        self.stream = None;
+    }
+}
+
+fn no_data(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+impl LinkCable for SocketLink {
+    fn exchange(&mut self, byte: u8) -> Option<u8> {
+        // Clear out anything left from earlier transfers, which also shows if the partner is back
+        let result = self.stream.as_ref()?.set_nonblocking(true).and_then(|_| self.wait_for_reply());
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        let result = result.and_then(|_| self.send(MESSAGE_TRANSFER, sequence, byte));
+
+        // Only wait for a partner that has been answering, otherwise take whatever is there now
+        let stream = self.stream.as_ref()?;
+        let result = result.and_then(|_| {
+            if self.partner_answering {
+                stream.set_nonblocking(false).and_then(|_| stream.set_read_timeout(Some(self.timeout)))
+            } else {
+                Ok(())
+            }
+        });
+
+        match result.and_then(|_| self.wait_for_reply()) {
+            Ok(Some(reply)) => Some(reply),
+            Ok(None) => {
+                self.partner_answering = false;
+                None
+            }
+            Err(err) => {
+                self.disconnect(err);
+                None
+            }
+        }
+    }
+
+    fn poll_external(&mut self, reply: u8) -> Option<u8> {
+        if let Err(err) = self.stream.as_ref()?.set_nonblocking(true) {
+            self.disconnect(err);
+            return None;
+        }
+
+        match self.read_message() {
+            Ok((MESSAGE_TRANSFER, sequence, byte)) => {
+                if let Err(err) = self.send(MESSAGE_REPLY, sequence, reply) {
+                    self.disconnect(err);
+                    return None;
+                }
+                Some(byte)
+            }
+            Ok(_) => None,
+            Err(err) if no_data(&err) => None,
+            Err(err) => {
+                self.disconnect(err);
+                None
+            }
+        }
+    }
+}
+
+/// Which link cable backend to use
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum LinkKind {
+    #[default]
+    None,
+    Stdout,
+    Tcp { address: String, listen: bool },
+    Unix { path: PathBuf, listen: bool },
+}
+
+impl LinkKind {
+    /// Parses `none`, `stdout`, `tcp:<address>`, `tcp-listen:<address>`, `unix:<path>` or
+    /// `unix-listen:<path>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        match value {
+            "none" => return Ok(LinkKind::None),
+            "stdout" => return Ok(LinkKind::Stdout),
+            _ => {}
+        }
+
+        match value.split_once(':') {
+            Some(("tcp", address)) => Ok(LinkKind::Tcp { address: address.to_string(), listen: false }),
+            Some(("tcp-listen", address)) => Ok(LinkKind::Tcp { address: address.to_string(), listen: true }),
+            Some(("unix", path)) => Ok(LinkKind::Unix { path: path.into(), listen: false }),
+            Some(("unix-listen", path)) => Ok(LinkKind::Unix { path: path.into(), listen: true }),
+            _ => Err(format!("Unknown link {}, expected none, stdout, tcp:<address>, tcp-listen:<address>, unix:<path> or unix-listen:<path>", value)),
+        }
+    }
+
+    /// Connects the link cable. Listening waits until the partner connects.
+    pub fn open(&self) -> io::Result<Box<dyn LinkCable>> {
+        match self {
+            LinkKind::None => Ok(Box::new(NoPartner)),
+            LinkKind::Stdout => Ok(Box::new(StdoutCapture)),
+            LinkKind::Tcp { address, listen } => {
+                let stream = if *listen {
+                    let listener = TcpListener::bind(address)?;
+                    println!("Waiting for link partner on {}", address);
+                    listener.accept()?.0
+                } else {
+                    TcpStream::connect(address)?
+                };
+                stream.set_nodelay(true)?;
+                Ok(Box::new(SocketLink::new(LinkStream::Tcp(stream))))
+            }
+            #[cfg(unix)]
+            LinkKind::Unix { path, listen } => {
+                let stream = if *listen {
+                    let listener = UnixListener::bind(path)?;
+                    println!("Waiting for link partner on {}", path.display());
+                    listener.accept()?.0
+                } else {
+                    UnixStream::connect(path)?
+                };
+                Ok(Box::new(SocketLink::new(LinkStream::Unix(stream))))
+            }
+            #[cfg(not(unix))]
+            LinkKind::Unix { .. } => Err(io::Error::new(ErrorKind::Unsupported, "Unix sockets aren't supported on this platform")),
+        }
+    }
+}
+
+// https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+pub struct Serial {
+    link: Box<dyn LinkCable>,
+    /// T-cycles until a transfer using the internal clock has shifted all 8 bits
+    transfer_cycles_left: Option<u32>,
+    /// T-cycles until the link is next checked for an externally clocked transfer
+    poll_cycles_left: u32,
+    /// Every byte sent with the internal clock, test ROMs report their results this way
+    output: Vec<u8>,
+}
+
+impl Serial {
+    pub fn new(link: Box<dyn LinkCable>) -> Self {
+        Serial {
+            link,
+            transfer_cycles_left: None,
+            poll_cycles_left: POLL_CYCLES,
+            output: Vec::new(),
+        }
+    }
+
+    /// Puts the port back to its power on state. The link stays connected.
+    pub fn reset(&mut self) {
+        self.transfer_cycles_left = None;
+        self.poll_cycles_left = POLL_CYCLES;
+        self.output.clear();
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Runs the serial port for a single T-cycle of the CPU clock
+    pub fn step(&mut self, mem: &mut dyn MemoryController) {
+        let sc = mem.read_8_sys(ADDRESS_SC);
+        if std::mem::take(&mut mem.shared_data_mut().sc_written) {
+            // Writing SC starts a transfer, or cancels one when bit 7 is clear
+            self.transfer_cycles_left = if sc & 0x81 == 0x81 {
+                let fast = mem.shared_data().cgb_mode && (sc & 2) != 0;
+                Some(8 * if fast { FAST_BIT_CYCLES } else { BIT_CYCLES })
+            } else {
+                None
+            };
+        }
+
+        if let Some(cycles_left) = self.transfer_cycles_left {
+            if cycles_left > 1 {
+                self.transfer_cycles_left = Some(cycles_left - 1);
+            } else {
+                self.transfer_cycles_left = None;
+                let sent = mem.read_8_sys(ADDRESS_SB);
+                self.output.push(sent);
+                let received = self.link.exchange(sent);
+                finish_transfer(mem, received.unwrap_or(0xFF));
+            }
+            return;
+        }
+
+        self.poll_cycles_left -= 1;
+        if self.poll_cycles_left == 0 {
+            self.poll_cycles_left = POLL_CYCLES;
+            // The partner's clock shifts SB whether or not this side is ready, but a transfer
+            // only completes if one was started with the external clock
+            if let Some(received) = self.link.poll_external(mem.read_8_sys(ADDRESS_SB)) {
+                if sc & 0x81 == 0x80 {
+                    finish_transfer(mem, received);
+                }
+            }
+        }
+    }
+}
+
+fn finish_transfer(mem: &mut dyn MemoryController, received: u8) {
+    mem.write_8_sys(ADDRESS_SB, received);
+    mem.write_8_sys(ADDRESS_SC, mem.read_8_sys(ADDRESS_SC) & 0x7F);
+    mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 8);
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::{io::{Read, Write}, os::unix::net::UnixStream, thread, time::Duration};
+
+    use crate::{constants::*, memory::MemoryController, memory_controllers::basic_memory::BasicMemory};
+
+    use super::{LinkCable, LinkKind, NoPartner, Serial, BIT_CYCLES};
+    #[cfg(unix)]
+    use super::{LinkStream, SocketLink, LINK_TIMEOUT, MESSAGE_REPLY};
+
+    /// Partner that answers with the byte it was sent plus one
+    struct Increment;
+
+    impl LinkCable for Increment {
+        fn exchange(&mut self, byte: u8) -> Option<u8> {
+            Some(byte + 1)
+        }
+    }
+
+    fn step_n(serial: &mut Serial, mem: &mut dyn MemoryController, cycles: u32) {
+        for _ in 0..cycles {
+            serial.step(mem);
+        }
+    }
+
+    #[test]
+    fn internal_clock_transfer() {
+        let mut m = BasicMemory::default();
+        let mut serial = Serial::new(Box::new(Increment));
+        m.write_8(ADDRESS_SB, 0x41);
+        m.write_8(ADDRESS_SC, 0x81);
+
+        step_n(&mut serial, &mut m, 8 * BIT_CYCLES - 1);
+        assert_eq!(0x41, m.read_8(ADDRESS_SB));
+        assert_eq!(0x80, m.read_8(ADDRESS_SC) & 0x80);
+
+        step_n(&mut serial, &mut m, 1);
+        assert_eq!(0x42, m.read_8(ADDRESS_SB));
+        assert_eq!(0, m.read_8(ADDRESS_SC) & 0x80);
+        assert_eq!(8, m.read_8(ADDRESS_IF) & 8);
+        assert_eq!(&[0x41], serial.output());
+    }
+
+    #[test]
+    fn reset_drops_transfer_in_progress() {
+        let mut m = BasicMemory::default();
+        let mut serial = Serial::new(Box::new(Increment));
+        m.write_8(ADDRESS_SB, 0x41);
+        m.write_8(ADDRESS_SC, 0x81);
+        step_n(&mut serial, &mut m, 8 * BIT_CYCLES);
+        m.write_8(ADDRESS_SC, 0x81);
+        step_n(&mut serial, &mut m, 1);
+
+        serial.reset();
+        let mut m = BasicMemory::default();
+        step_n(&mut serial, &mut m, 8 * BIT_CYCLES);
+        assert_eq!(0, m.read_8(ADDRESS_IF) & 8);
+        assert!(serial.output().is_empty());
+    }
+
+    #[test]
+    fn no_partner_reads_ff() {
+        let mut m = BasicMemory::default();
+        let mut serial = Serial::new(Box::new(NoPartner));
+        m.write_8(ADDRESS_SB, 0x41);
+        m.write_8(ADDRESS_SC, 0x81);
+        step_n(&mut serial, &mut m, 8 * BIT_CYCLES);
+        assert_eq!(0xFF, m.read_8(ADDRESS_SB));
+
+        // Nothing ever provides an external clock
+        m.write_8(ADDRESS_IF, 0);
+        m.write_8(ADDRESS_SC, 0x80);
+        step_n(&mut serial, &mut m, 16 * BIT_CYCLES);
+        assert_eq!(0x80, m.read_8(ADDRESS_SC) & 0x80);
+        assert_eq!(0, m.read_8(ADDRESS_IF) & 8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_link_exchanges_bytes() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut clock_side = SocketLink::new(LinkStream::Unix(a));
+        let mut external_side = SocketLink::new(LinkStream::Unix(b));
+
+        let partner = thread::spawn(move || loop {
+            if let Some(byte) = external_side.poll_external(0x99) {
+                return byte;
+            }
+        });
+
+        assert_eq!(Some(0x99), clock_side.exchange(0x42));
+        assert_eq!(0x42, partner.join().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_link_ignores_late_reply() {
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let mut clock_side = SocketLink::new(LinkStream::Unix(a));
+        clock_side.timeout = Duration::from_millis(10);
+        assert_eq!(None, clock_side.exchange(0x42));
+
+        // The partner gets to the first transfer after it timed out
+        let mut transfer = [0; 3];
+        b.read_exact(&mut transfer).unwrap();
+        b.write_all(&[MESSAGE_REPLY, transfer[1], 0x11]).unwrap();
+        let mut external_side = SocketLink::new(LinkStream::Unix(b));
+        let partner = thread::spawn(move || loop {
+            if let Some(byte) = external_side.poll_external(0x99) {
+                return byte;
+            }
+        });
+
+        clock_side.timeout = LINK_TIMEOUT;
+        assert_eq!(Some(0x99), clock_side.exchange(0x43));
+        assert_eq!(0x43, partner.join().unwrap());
+    }
+
+    #[test]
+    fn parse_link_kind() {
+        assert_eq!(Ok(LinkKind::Stdout), LinkKind::parse("stdout"));
+        assert_eq!(
+            Ok(LinkKind::Tcp { address: "127.0.0.1:8765".to_string(), listen: true }),
+            LinkKind::parse("tcp-listen:127.0.0.1:8765")
+        );
+        assert_eq!(
+            Ok(LinkKind::Unix { path: "/tmp/gb".into(), listen: false }),
+            LinkKind::parse("unix:/tmp/gb")
+        );
+        assert!(LinkKind::parse("usb").is_err());
+    }
+}
@@ -1,38 +1,75 @@
 use std::{
-    collections::VecDeque,
     panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    thread,
     time::{Duration, Instant},
 };
 
-use morton_encoding::morton_encode;
+use macroquad::input::{is_quit_requested, prevent_quit};
 
 use crate::{
-    constants::*, debug::{console::DebugConsole, flags::DEBUG_TRY_UNWIND_PROCESS_INSTRUCTION, metrics::DebugMetrics, watch::{Watch, WatchFn, WatchValueChange}}, lcd::Lcd, memory::MemoryController, memory_controllers::basic_memory::BasicMemory, model::model_render::{OamScanData, PixelRenderData, PpuData}, opcodes::{process_instruction, u16_to_u8s}
+    boot_rom::set_post_boot_state, cartridge_header::{CartridgeHeader, CgbSupport, CartridgeHeaderError, CartridgeType, MbcKind}, config::Config, constants::*, debug::{console::DebugConsole, flags::DEBUG_TRY_UNWIND_PROCESS_INSTRUCTION, metrics::DebugMetrics, watch::{Watch, WatchFn, WatchValueChange}}, dma::{step_oam_dma, step_vram_dma}, apu::Apu, audio::AudioOutput, input::Input, lcd::{Display, Lcd}, memory::MemoryController, memory_controllers::{basic_memory::BasicMemory, mbc1::Mbc1, mbc2::Mbc2, mbc3::Mbc3, mbc5::Mbc5}, opcodes::{process_instruction, u16_to_u8s}, ppu::Ppu, save::SaveFile, serial::{NoPartner, Serial}, timer::Timer
 };
 
-pub async fn boot(rom: Vec<u8>) {
-    let mbc_type = rom[0x147];
-    let mut mem: Box<dyn MemoryController>;
+pub async fn boot(rom: Vec<u8>, boot_rom: Option<Vec<u8>>, save_path: PathBuf, config: Config) -> Result<(), CartridgeHeaderError> {
+    let link = config.serial_link.open().unwrap_or_else(|err| {
+        println!("Failed to connect the link cable, continuing without it: {}", err);
+        Box::new(NoPartner)
+    });
+    let mut emulator = Emulator::new(rom, boot_rom, Lcd::new(config.color_scheme), Serial::new(link))?;
+    println!("{}", emulator.header());
+    if !emulator.header().header_checksum_valid {
+        println!("Warning: header checksum does not match, real hardware would refuse to boot this ROM");
+    }
+    emulator.enable_debug_console();
+
+    let mut input = Input::new(config.key_bindings);
+    let mut display = Display::new(&emulator.lcd);
+    let mut audio = AudioOutput::new();
 
-    match mbc_type {
-        0 => {
-            mem = Box::new(BasicMemory::new(rom));
+    loop {
+        let save_file = if emulator.header().cartridge_type.has_battery {
+            // A real cartridge's clock keeps running while it's switched off
+            emulator.mem().shared_data_mut().rtc_catch_up = true;
+            let save_file = SaveFile::new(save_path.clone());
+            save_file.load(emulator.mem());
+            Some(save_file)
+        } else {
+            None
+        };
+
+        match run_loop(&mut emulator, save_file, &mut input, &mut display, &mut audio).await {
+            RunResult::Quit => return Ok(()),
+            RunResult::Reset => {
+                println!("Resetting");
+                emulator.reset();
+            }
         }
-        _ => todo!(
-            "Need to implement more mbc types. Tried to use: {:#x}",
-            mbc_type
-        ),
     }
+}
 
-    mem.r().sp = ADDRESS_STACK_START;
-    mem.write_8(ADDRESS_LCDC, 0x83);
-    *mem.ime() = false;
-    // skip boot ROM and go straight to game ROM
-    mem.r().pc = 0x0100;
+fn create_memory_controller(rom: Vec<u8>, cartridge_type: CartridgeType) -> Result<Box<dyn MemoryController>, CartridgeHeaderError> {
+    Ok(match cartridge_type.mbc {
+        MbcKind::None => Box::new(BasicMemory::new(rom, cartridge_type.has_ram)),
+        MbcKind::Mbc1 => Box::new(Mbc1::new(rom)),
+        MbcKind::Mbc2 => Box::new(Mbc2::new(rom)),
+        MbcKind::Mbc3 => Box::new(Mbc3::new(rom, cartridge_type.has_timer)),
+        MbcKind::Mbc5 => Box::new(Mbc5::new(rom, cartridge_type.has_rumble)),
+        mbc => return Err(CartridgeHeaderError::UnsupportedMbc(mbc)),
+    })
+}
 
-    run_loop(&mut *mem).await;
+/// Why the run loop stopped
+enum RunResult {
+    Quit,
+    /// The reset button was pressed, the console should be started again from power on
+    Reset,
 }
 
+/// Time the real hardware takes to draw one frame
+const FRAME_DURATION: Duration =
+    Duration::from_nanos(T_CYCLES_PER_FRAME * 1_000_000_000 / T_CYCLES_PER_SECOND);
+
 fn create_watches() -> Vec<Box<dyn Watch>> {
     vec![
         // WatchFn::new(
@@ -44,472 +81,299 @@ fn create_watches() -> Vec<Box<dyn Watch>> {
     ]
 }
 
-async fn run_loop(mem: &mut dyn MemoryController) {
-    let mut ime_actually_enabled = false;
-    let mut ime_actually_enable_next = false;
-    let mut time_next_instruction = Instant::now();
-    let mut watches = create_watches();
-    let mut metrics = DebugMetrics::new();
-
-    let mut time_next_ppu = Instant::now();
-    let mut dots_left = 1;
-    let mut oam_scan = OamScanData {
-        current_object: 0,
-        objects: VecDeque::new(),
-    };
-    let mut pixel_render = PixelRenderData::new();
-    let mut first_dot_after_switch = false;
-    let mut lcd = Lcd::new();
-    let mut last_stat_interrupt_state = false;
-    let mut ppu_data: VecDeque<PpuData> = VecDeque::new();
-
-    let mut time_next_div = Instant::now();
-
-    let mut time_next_timer = Instant::now();
+/// What happened during one `Emulator::step`
+pub struct StepResult {
+    /// Machine cycles the step took
+    pub cycles: u64,
+    /// The PPU finished a frame and the LCD holds a complete picture
+    pub frame_ready: bool,
+}
 
-    let mut debug_console = DebugConsole::new();
+/// The console without any of the windowing, audio output or input around it, so it can run
+/// inside a window or headless
+pub struct Emulator {
+    rom: Vec<u8>,
+    boot_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+    mem: Box<dyn MemoryController>,
+    pub lcd: Lcd,
+    pub serial: Serial,
+    pub apu: Apu,
+    ppu: Ppu,
+    timer: Timer,
+    ime_actually_enabled: bool,
+    ime_actually_enable_next: bool,
+    cartridge_half_cycle: bool,
+    watches: Vec<Box<dyn Watch>>,
+    metrics: DebugMetrics,
+    debug_console: Option<DebugConsole>,
+}
 
-    loop {
-        let now = Instant::now();
-        let mut interrupt_triggered = false;
+impl Emulator {
+    pub fn new(rom: Vec<u8>, boot_rom: Option<Vec<u8>>, lcd: Lcd, serial: Serial) -> Result<Self, CartridgeHeaderError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let mem = create_memory_controller(rom.clone(), header.cartridge_type)?;
+
+        let mut emulator = Emulator {
+            rom,
+            boot_rom,
+            header,
+            mem,
+            lcd,
+            serial,
+            apu: Apu::new(),
+            ppu: Ppu::new(),
+            timer: Timer::new(),
+            ime_actually_enabled: false,
+            ime_actually_enable_next: false,
+            cartridge_half_cycle: false,
+            watches: create_watches(),
+            metrics: DebugMetrics::new(),
+            debug_console: None,
+        };
+        emulator.reset();
+        Ok(emulator)
+    }
 
-        if now >= time_next_div {
-            mem.write_8_sys(ADDRESS_DIV, mem.read_8_sys(ADDRESS_DIV).wrapping_add(1));
-            time_next_div = now.checked_add(Duration::from_nanos(61035)).unwrap();
+    /// Starts the console again from power on. The LCD and link cable connection are kept.
+    pub fn reset(&mut self) {
+        self.mem = create_memory_controller(self.rom.clone(), self.header.cartridge_type)
+            .expect("the cartridge type was checked when the emulator was created");
+        // Only the DMG boot ROM can be loaded, and it leaves the console in DMG mode
+        self.mem.shared_data_mut().cgb_mode = self.boot_rom.is_none() && self.header.cgb_support != CgbSupport::DmgOnly;
+
+        match &self.boot_rom {
+            // The boot ROM starts at 0 with everything cleared and sets up the rest itself
+            Some(boot_rom) => self.mem.shared_data_mut().boot_rom = Some(boot_rom.clone()),
+            // skip boot ROM and go straight to game ROM
+            None => set_post_boot_state(&mut *self.mem, self.header.header_checksum),
         }
 
-        if now >= time_next_timer {
-            let tac = mem.read_8_sys(ADDRESS_TAC);
-            if (tac & 4) != 0 {
-                let tima = mem.read_8_sys(ADDRESS_TIMA);
-                if tima == 0xFF {
-                    mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 4);
-                    mem.write_8_sys(ADDRESS_TIMA, mem.read_8_sys(ADDRESS_TMA));
-                } else {
-                    mem.write_8_sys(ADDRESS_TIMA, tima + 1);
-                }
+        self.ppu = Ppu::new();
+        self.timer = Timer::with_div(self.mem.read_8_sys(ADDRESS_DIV));
+        self.apu = Apu::new();
+        self.serial.reset();
+        self.ime_actually_enabled = false;
+        self.ime_actually_enable_next = false;
+        self.cartridge_half_cycle = false;
+    }
 
-                let duration = match tac & 3 {
-                    0 => Duration::from_nanos(244141),
-                    1 => Duration::from_nanos(3815),
-                    2 => Duration::from_nanos(15259),
-                    3 => Duration::from_nanos(61035),
-                    _ => panic!("Invalid timer clock select value"),
-                };
-                time_next_timer = now.checked_add(duration).unwrap();
-            }
-        }
+    /// Reads debug commands from stdin between instructions
+    pub fn enable_debug_console(&mut self) {
+        self.debug_console = Some(DebugConsole::new());
+    }
 
-        if now >= time_next_instruction {
-            debug_console.run(mem, &mut metrics);
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
 
-            mem.process_input();
+    pub fn mem(&mut self) -> &mut dyn MemoryController {
+        &mut *self.mem
+    }
 
-            if ime_actually_enabled {
-                // Check interrupts
-                let interrupt_requests = mem.read_8(ADDRESS_IF);
-                let interrupt_enabled = mem.read_8(ADDRESS_IE);
+    /// Runs one instruction or interrupt dispatch and catches the rest of the system up to it
+    pub fn step(&mut self) -> StepResult {
+        let mem = &mut *self.mem;
+        let metrics = &mut self.metrics;
 
-                for i in 0..5 {
-                    let interrupt_can_start = interrupt_requests & interrupt_enabled;
-                    if interrupt_can_start & (1 << i) != 0 {
-                        *mem.ime() = false;
-                        mem.write_8(ADDRESS_IF, interrupt_requests & !(1 << i));
+        let mut interrupt_triggered = false;
+        // machine cycles taken by this step
+        let mut cycles = 0;
 
-                        let pc_vals = u16_to_u8s(mem.r().pc);
-                        mem.write_8(mem.r_i().sp - 1, pc_vals.0);
-                        mem.write_8(mem.r_i().sp - 2, pc_vals.1);
-                        mem.r().sp -= 2;
+        if let Some(debug_console) = self.debug_console.as_mut() {
+            debug_console.run(mem, metrics);
+        }
 
-                        mem.r().pc = ADDRESS_FIRST_INTERRUPT_HANDLER + i * 0x08;
-                        wait_cycles(5, &mut time_next_instruction, &now);
-                        interrupt_triggered = true;
-                        break;
-                    }
-                }
-            }
+        mem.process_input();
 
-            let dma_source_address = mem.shared_data().dma_source_address;
-            if dma_source_address >= 0x8000 && dma_source_address < 0xE000 {
-                let offset = dma_source_address & 0xFF;
-                if offset <= 0x9F {
-                    mem.write_8(dma_source_address, mem.read_8(ADDRESS_OAM_START + offset));
-                    mem.shared_data_mut().dma_source_address += 1;
+        // A pending interrupt wakes the CPU from HALT even when IME is off, it just isn't serviced
+        let interrupt_pending = (mem.read_8(ADDRESS_IE) & mem.read_8(ADDRESS_IF) & 0x1F) != 0;
+        if interrupt_pending {
+            mem.shared_data_mut().halted = false;
+        }
+        // Any pressed button in a selected group pulls its JOYP line low and ends STOP
+        if mem.shared_data().stopped && (mem.read_8(ADDRESS_JOYP) & 0x0F) != 0x0F {
+            mem.shared_data_mut().stopped = false;
+        }
+        let stopped = mem.shared_data().stopped;
+
+        if self.ime_actually_enabled && !stopped {
+            // Check interrupts
+            let interrupt_requests = mem.read_8(ADDRESS_IF);
+            let interrupt_enabled = mem.read_8(ADDRESS_IE);
+
+            for i in 0..5 {
+                let interrupt_can_start = interrupt_requests & interrupt_enabled;
+                if interrupt_can_start & (1 << i) != 0 {
+                    *mem.ime() = false;
+                    mem.write_8(ADDRESS_IF, interrupt_requests & !(1 << i));
+
+                    let pc_vals = u16_to_u8s(mem.r().pc);
+                    mem.write_8(mem.r_i().sp - 1, pc_vals.0);
+                    mem.write_8(mem.r_i().sp - 2, pc_vals.1);
+                    mem.r().sp -= 2;
+
+                    mem.r().pc = ADDRESS_FIRST_INTERRUPT_HANDLER + i * 0x08;
+                    cycles = 5;
+                    interrupt_triggered = true;
+                    break;
                 }
             }
+        }
 
-            if !interrupt_triggered {
-                let pc = mem.r_i().pc;
-                let cycles = if DEBUG_TRY_UNWIND_PROCESS_INSTRUCTION {
-                    let result = panic::catch_unwind(AssertUnwindSafe(|| process_instruction(mem, &mut metrics)));
-                    match result {
-                        Ok(c) => {
-                            c
-                        }
-                        Err(_) => {
-                            let current_instruction = mem.read_8(pc);
-                            println!("Caught an unwind from process_instruction. Instruction that triggered the panic pc: {:#x}, ins: {:#b}", pc, current_instruction);
-                            println!("Register state after the panic: {:?}", mem.r_i());
-                            panic!("Repanicing after caught an unwind from process_instruction");
-                        }
+        if !interrupt_triggered && (mem.shared_data().halted || stopped) {
+            // The CPU doesn't execute anything but the rest of the system keeps running
+            cycles = 1;
+        } else if !interrupt_triggered {
+            let pc = mem.r_i().pc;
+            cycles = if DEBUG_TRY_UNWIND_PROCESS_INSTRUCTION {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| process_instruction(mem, metrics)));
+                match result {
+                    Ok(c) => {
+                        c
                     }
-                } else {
-                    process_instruction(mem, &mut metrics)
-                };
-
-                for watch in &mut watches {
-                    if watch.test(mem) {
+                    Err(_) => {
                         let current_instruction = mem.read_8(pc);
-                        println!("{} triggered after process_instruction. Instruction that triggered pc: {:#x}, ins: {:#b}.", watch.name(), pc, current_instruction);
-                        println!("Register state after watch triggered: {:?}", mem.r_i());
+                        println!("Caught an unwind from process_instruction. Instruction that triggered the panic pc: {:#x}, ins: {:#b}", pc, current_instruction);
+                        println!("Register state after the panic: {:?}", mem.r_i());
+                        panic!("Repanicing after caught an unwind from process_instruction");
                     }
                 }
-
-                wait_cycles(cycles, &mut time_next_instruction, &now);
-            }
-
-            if !*mem.ime() {
-                ime_actually_enabled = false;
-                ime_actually_enable_next = false;
-            } else if !ime_actually_enabled {
-                if ime_actually_enable_next {
-                    ime_actually_enabled = true;
-                    ime_actually_enable_next = false;
-                } else {
-                    ime_actually_enable_next = true;
+            } else {
+                process_instruction(mem, metrics)
+            };
+
+            for watch in &mut self.watches {
+                if watch.test(mem) {
+                    let current_instruction = mem.read_8(pc);
+                    println!("{} triggered after process_instruction. Instruction that triggered pc: {:#x}, ins: {:#b}.", watch.name(), pc, current_instruction);
+                    println!("Register state after watch triggered: {:?}", mem.r_i());
                 }
             }
         }
 
-        if now >= time_next_ppu {
-            dots_left -= 1;
-            let reset_first_dot_flag = first_dot_after_switch;
-
-            let mut stat = mem.read_8(ADDRESS_STAT);
-            let mut ppu_mode = stat & 0b00000011;
-
-            if crate::debug::flags::DEBUG_PRINT_PPU {
-                println!("dots_left: {}", dots_left);
-                println!("ppu_mode: {}", ppu_mode);
+        if !*mem.ime() {
+            self.ime_actually_enabled = false;
+            self.ime_actually_enable_next = false;
+        } else if !self.ime_actually_enabled {
+            if self.ime_actually_enable_next {
+                self.ime_actually_enabled = true;
+                self.ime_actually_enable_next = false;
+            } else {
+                self.ime_actually_enable_next = true;
             }
+        }
 
-            let ly = mem.read_8(ADDRESS_LY);
-            if ppu_data.len() >= 600 {
-                ppu_data.pop_front();
+        let double_speed = mem.shared_data().double_speed;
+
+        // Catch the rest of the system up to the CPU. Every component is stepped off the cycles
+        // the CPU reports instead of the wall clock so they stay in sync and runs are reproducible.
+        let mut frame_ready = false;
+        let mut m_cycle = 0;
+        while m_cycle < cycles {
+            m_cycle += 1;
+            step_oam_dma(mem);
+            // The CPU is paused while VRAM DMA copies but everything else keeps running
+            cycles += step_vram_dma(mem);
+
+            for t_cycle in 0..T_CYCLES_PER_M_CYCLE {
+                // The divider and APU are stopped along with the CPU clock in STOP mode
+                if !stopped {
+                    self.timer.step(mem);
+                    self.serial.step(mem);
+                }
+                // In double speed the PPU and APU keep their normal clock, so they only get
+                // every other CPU T-cycle
+                if double_speed && !t_cycle.is_multiple_of(2) {
+                    continue;
+                }
+                if !stopped {
+                    self.apu.step(mem);
+                }
+                frame_ready |= self.ppu.step(mem, &mut self.lcd);
             }
-            ppu_data.push_back(PpuData { mode: ppu_mode, dots_left, ly });
-
-            match ppu_mode {
-                PPU_MODE_OAM_SCAN => {
-                    if first_dot_after_switch {
-                        oam_scan.current_object = 0;
-                        oam_scan.objects.clear();
-                    }
-
-                    if dots_left % 2 == 0 && oam_scan.objects.len() < 10 {
-                        let lcdc = mem.read_8(ADDRESS_LCDC);
-                        let obj_height: u8 = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
-                        let ly = mem.read_8(ADDRESS_LY);
+        }
 
-                        let obj_addr = ADDRESS_OAM_START + 4 * oam_scan.current_object;
-                        let obj_y = mem.read_8(obj_addr);
+        if double_speed {
+            // The cartridge's clock runs off real time, which passes half as fast per CPU cycle
+            let cartridge_cycles = cycles + self.cartridge_half_cycle as u64;
+            self.cartridge_half_cycle = !cartridge_cycles.is_multiple_of(2);
+            mem.tick_cartridge(cartridge_cycles / 2);
+        } else {
+            mem.tick_cartridge(cycles);
+        }
 
-                        if obj_on_screen(ly, obj_y, obj_height) {
-                            oam_scan.objects.push_back(obj_addr);
-                        }
+        StepResult { cycles, frame_ready }
+    }
+}
 
-                        oam_scan.current_object += 1;
-                    }
+async fn run_loop(
+    emulator: &mut Emulator,
+    mut save_file: Option<SaveFile>,
+    input: &mut Input,
+    display: &mut Display,
+    audio: &mut AudioOutput,
+) -> RunResult {
+    let mut time_next_frame = Instant::now();
 
-                    if dots_left == 0 {
-                        // should be 172? Depends on how the delays are added later.
-                        dots_left = 160;
-                        // + 1 will change mode from 2 to 3
-                        mem.write_8_sys(ADDRESS_STAT, stat + 1);
-                        first_dot_after_switch = true;
-                    }
-                }
-                PPU_MODE_RENDER_PIXEL => {
-                    if first_dot_after_switch {
-                        pixel_render.reset();
-                    }
+    // quitting is handled at the end of a frame so the save can be flushed first
+    prevent_quit();
 
-                    if pixel_render.x < 160 {
-                        let lcdc = mem.read_8(ADDRESS_LCDC);
-                        let ly = mem.read_8(ADDRESS_LY);
-
-                        if ly >= 144 {
-                            println!("ly is {} in PPU_MODE_RENDER_PIXEL. PPU data: {:?}", ly, ppu_data);
-                            panic!("ly is {} in PPU_MODE_RENDER_PIXEL", ly)
-                        }
-                        // todo: use palettes
-                        if !pixel_render.background_queue.len() >= 8 {
-                            let scx = mem.read_8(ADDRESS_SCX);
-                            let scy = mem.read_8(ADDRESS_SCY);
-
-                            // todo: pandocs suggest this should be broken out into an operation over multiple dots
-                            let tiledata_index_address: u16;
-                            let window_enabled = (lcdc & LCDC_WINDOW_ENABLE) != 0;
-                            if window_enabled {
-                                todo!("Window not implemented")
-                            } else {
-                                let tilemap_address = if (lcdc & LCDC_BG_TILEMAP) != 0 {
-                                    ADDRESS_TILEMAP_2
-                                } else {
-                                    ADDRESS_TILEMAP_1
-                                };
-                                // https://gbdev.io/pandocs/pixel_fifo.html gives this Y coord code. Doesn't seem right at all. Misintepreting the docs?
-                                // let x = ((scx / 8) + pixel_render.tile_x) % 32;
-                                // let y = ly.wrapping_add(scy);
-                                let x = ((scx / 8) + pixel_render.tile_x / 8) % 32;
-                                let y = scy / 8 + ly / 8;
-
-                                tiledata_index_address = tilemap_address + x as u16 + y as u16 * 32;
-                            }
-
-                            let mut tile_data_index = mem.read_8(tiledata_index_address);
-                            let tile_data_address_mode_easy =
-                                (lcdc & LCDC_BG_AND_WINDOW_TILEDATA) != 0;
-                            let tile_data_address = if tile_data_address_mode_easy {
-                                ADDRESS_TILEDATA_1 + tile_data_index as u16 * 16
-                            } else {
-                                if tile_data_index > 127 {
-                                    tile_data_index -= 128;
-                                } else {
-                                    tile_data_index += 128;
-                                }
-
-                                // ADDRESS_TILEDATA_2 should be 0x8800 not 0x9000 like documentation will give because
-                                // this code is not using signed numbers for the index.
-                                ADDRESS_TILEDATA_2 + tile_data_index as u16 * 16
-                            };
-
-                            let tile_low = mem.read_8(tile_data_address);
-                            let tile_high = mem.read_8(tile_data_address + 1);
-
-                            let mut all_pixel_data = morton_encode([tile_high, tile_low]);
-
-                            for _i in 0..8 {
-                                let pixel = ((all_pixel_data & 0xC000) >> 14) as u8;
-                                pixel_render.background_queue.push_back(pixel);
-                                all_pixel_data <<= 2;
-                            }
-                        }
-
-                        if !oam_scan.objects.is_empty() {
-                            let obj_addr = *oam_scan.objects.front().unwrap();
-                            let obj_x = mem.read_8(obj_addr + 1);
-
-                            let tall_tiles = (lcdc & LCDC_OBJ_SIZE) != 0;
-                            if tall_tiles {
-                                todo!("Implement 8x16 tile addressing")
-                            }
-
-                            // account for 8 pixel offset compared to screen
-                            if pixel_render.x + 8 == obj_x {
-                                oam_scan.objects.pop_front().unwrap();
-                                let obj_y = mem.read_8(obj_addr);
-                                let obj_index = mem.read_8(obj_addr + 2);
-                                let obj_attrs = mem.read_8(obj_addr + 3);
-                                let row_in_tile_offset =
-                                    obj_y + if tall_tiles { 16 } else { 8 } - 16 - ly;
-                                let tile_data_address = ADDRESS_TILEDATA_1
-                                    + obj_index as u16 * 16
-                                    + row_in_tile_offset as u16 * 2;
-
-                                // todo: check x and y flip
-                                let tile_low = mem.read_8(tile_data_address);
-                                let tile_high = mem.read_8(tile_data_address + 1);
-
-                                let mut all_pixel_data = morton_encode([tile_high, tile_low]);
-
-                                let obj_queue_len = pixel_render.obj_queue.len();
-                                pixel_render.obj_queue.make_contiguous();
-                                let queue_contents = pixel_render.obj_queue.as_mut_slices().0;
-
-                                let priority_data = if (obj_attrs & 1 << 7) != 0 { 4 } else { 0 };
-                                for i in 0..8 {
-                                    let pixel =
-                                        ((all_pixel_data & 0xC000) >> 14) as u8 | priority_data;
-
-                                    if i < obj_queue_len {
-                                        // pixel in queue is transparent or behind bg
-                                        if queue_contents[i] & 3 == 0 || queue_contents[i] & 4 != 0
-                                        {
-                                            queue_contents[i] = pixel;
-                                        }
-                                    } else {
-                                        pixel_render.background_queue.push_back(pixel);
-                                    }
-
-                                    all_pixel_data <<= 2;
-                                }
-                            }
-                        }
-
-                        // actually draw a pixel now
-                        let bg = pixel_render.background_queue.pop_front();
-                        let obj = pixel_render.obj_queue.pop_front();
-                        let bg_disabled = lcdc & LCDC_BG_WINDOW_ENABLE != 0;
-                        match (bg, obj) {
-                            (Some(bgv), Some(objv)) => {
-                                let obj_low_priority = objv & 4 != 0;
-                                let obj_color = objv & 3;
-                                let bg_color = if bg_disabled { 0 } else { bgv };
-
-                                if obj_color == 0 || obj_low_priority {
-                                    lcd.draw_pixel(pixel_render.x, ly, bg_color);
-                                } else {
-                                    lcd.draw_pixel(pixel_render.x, ly, obj_color);
-                                }
-                            }
-                            (Some(bgv), None) => {
-                                let bg_color = if bg_disabled { 0 } else { bgv };
-                                lcd.draw_pixel(pixel_render.x, ly, bg_color);
-                            }
-                            (None, Some(objv)) => {
-                                let obj_low_priority = objv & 4 != 0;
-                                let obj_color = objv & 3;
-
-                                if obj_color != 0 && !obj_low_priority {
-                                    lcd.draw_pixel(pixel_render.x, ly, obj_color);
-                                }
-                            }
-                            _ => {}
-                        }
-
-                        pixel_render.x += 1;
-                    }
+    loop {
+        if emulator.step().frame_ready {
+            // Real-time pacing only happens here. Emulation runs as fast as it can within a frame.
+            time_next_frame += FRAME_DURATION;
+            let now = Instant::now();
+            if time_next_frame > now {
+                thread::sleep(time_next_frame - now);
+            } else if now - time_next_frame > FRAME_DURATION * 4 {
+                // Fell far behind, likely paused in the debug console. Don't try to catch up.
+                time_next_frame = now;
+            }
 
-                    if dots_left == 0 {
-                        // transition to horiz blank
-                        dots_left = 216;
-                        // - 3 will change mode from 3 to 0
-                        mem.write_8_sys(ADDRESS_STAT, stat - 3);
-                        first_dot_after_switch = true;
-                    }
-                }
-                PPU_MODE_HORIZ_BLANK => {
-                    if dots_left == 0 {
-                        let ly = mem.read_8(ADDRESS_LY);
-                        if ly == 143 {
-                            // transition to vertical blank
-                            dots_left = 456;
-                            // + 1 will change mode from 0 to 1
-                            mem.write_8_sys(ADDRESS_STAT, stat + 1);
-                            mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 1);
-                        } else {
-                            // transition to OAM scan
-                            dots_left = 80;
-                            // + 2 will change mode from 0 to 2
-                            mem.write_8_sys(ADDRESS_STAT, stat + 2);
-                        }
-
-                        mem.write_8_sys(ADDRESS_LY, ly + 1);
-                        first_dot_after_switch = true;
-                    }
-                }
-                PPU_MODE_VERT_BLANK => {
-                    if first_dot_after_switch {
-                        lcd.show_frame().await;
-                    }
+            audio.play(emulator.apu.take_samples());
+            display.show_frame(&emulator.lcd).await;
 
-                    if dots_left == 0 {
-                        let ly = mem.read_8(ADDRESS_LY);
-                        if ly == 153 {
-                            // transition to OAM scan
-                            dots_left = 80;
-                            // + 1 will change mode from 1 to 2
-                            mem.write_8_sys(ADDRESS_STAT, stat + 1);
-                            mem.write_8_sys(ADDRESS_LY, 0);
-                            first_dot_after_switch = true;
-                            lcd.start_new_frame();
-                        } else {
-                            dots_left = 456;
-                            mem.write_8_sys(ADDRESS_LY, ly + 1);
-                        }
-                    }
-                }
-                _ => panic!("Invalid ppu_mode"),
+            if let Some(save_file) = save_file.as_mut() {
+                save_file.on_frame(emulator.mem());
             }
 
-            // get the latest values
-            let ly = mem.read_8_sys(ADDRESS_LY);
-            let lyc = mem.read_8_sys(ADDRESS_LYC);
-            stat = mem.read_8(ADDRESS_STAT);
-            ppu_mode = stat & 0b00000011;
-            let ly_match = ly == lyc;
-            if (ly_match && (stat & 1 << 6) != 0)
-                || (ppu_mode == 2 && (stat & 1 << 5) != 0)
-                || (ppu_mode == 1 && (stat & 1 << 4) != 0)
-                || (ppu_mode == 0 && (stat & 1 << 3) != 0)
-            {
-                if !last_stat_interrupt_state {
-                    last_stat_interrupt_state = true;
-                    mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 2);
-                }
-            } else {
-                last_stat_interrupt_state = false;
-            }
+            let reset_pressed = input.poll(emulator.mem());
 
-            if ly_match != ((stat & 1 << 2) != 0) {
-                if ly_match {
-                    mem.write_8_sys(ADDRESS_STAT, stat | 1 << 2);
-                } else {
-                    mem.write_8_sys(ADDRESS_STAT, stat & !(1 << 2));
+            if is_quit_requested() || reset_pressed {
+                if let Some(save_file) = save_file.as_mut() {
+                    save_file.flush(emulator.mem());
                 }
-            }
-
-            // this has to go really fast... may need refactoring to keep up?
-            time_next_ppu = now.checked_add(Duration::from_nanos(238)).unwrap();
-            if reset_first_dot_flag {
-                first_dot_after_switch = false;
+                return if reset_pressed { RunResult::Reset } else { RunResult::Quit };
             }
         }
     }
 }
 
-fn wait_cycles(cycles: u64, next_instruction: &mut Instant, now: &Instant) {
-    *next_instruction = match now.checked_add(Duration::from_nanos(954 * cycles)) {
-        Some(i) => i,
-        None => panic!("Could not set instant for next instruction"),
-    }
-}
-
-pub fn obj_on_screen(ly: u8, obj_y: u8, obj_height: u8) -> bool {
-    let top_above = obj_y <= ly + 16;
-    let bottom_below = obj_y + obj_height > ly + 16;
-    top_above && bottom_below
-}
-
 #[cfg(test)]
 mod tests {
     use rstest::rstest;
 
-    use super::obj_on_screen;
+    use crate::{boot_rom::BOOT_ROM_SIZE, cartridge_header::{CartridgeHeaderError, MbcKind}, constants::{ADDRESS_CARTRIDGE_TYPE, ADDRESS_CGB_FLAG}, lcd::Lcd, serial::{NoPartner, Serial}};
+
+    use super::Emulator;
+
+    #[test]
+    fn unsupported_mbc_is_an_error() {
+        let mut rom = vec![0; 0x8000];
+        // MBC6
+        rom[ADDRESS_CARTRIDGE_TYPE as usize] = 0x20;
+        let result = Emulator::new(rom, None, Lcd::new(Default::default()), Serial::new(Box::new(NoPartner)));
+        assert!(matches!(result, Err(CartridgeHeaderError::UnsupportedMbc(MbcKind::Mbc6))));
+    }
 
     #[rstest]
-    #[case(0, 0, 8, false)]
-    #[case(0, 0, 16, false)]
-    #[case(0, 2, 8, false)]
-    #[case(0, 2, 16, true)]
-    #[case(0, 16, 8, true)]
-    #[case(0, 16, 16, true)]
-    #[case(143, 144, 8, false)]
-    #[case(143, 144, 16, true)]
-    #[case(143, 152, 8, true)]
-    #[case(143, 152, 16, true)]
-    #[case(143, 154, 8, true)]
-    #[case(143, 154, 16, true)]
-    #[case(143, 160, 8, false)]
-    #[case(143, 160, 16, false)]
-    fn obj_on_screen_test(
-        #[case] ly: u8,
-        #[case] obj_y: u8,
-        #[case] obj_height: u8,
-        #[case] expected_result: bool,
-    ) {
-        let result = obj_on_screen(ly, obj_y, obj_height);
-        assert_eq!(expected_result, result);
+    #[case(None, true)]
+    #[case(Some(vec![0; BOOT_ROM_SIZE]), false)]
+    fn dmg_boot_rom_forces_dmg_mode(#[case] boot_rom: Option<Vec<u8>>, #[case] expected: bool) {
+        let mut rom = vec![0; 0x8000];
+        rom[ADDRESS_CGB_FLAG as usize] = 0x80;
+        let mut emulator = Emulator::new(rom, boot_rom, Lcd::new(Default::default()), Serial::new(Box::new(NoPartner))).unwrap();
+        assert_eq!(expected, emulator.mem().shared_data().cgb_mode);
     }
 }
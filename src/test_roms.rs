@@ -0,0 +1,186 @@
+//! Runs the test ROM suites from https://github.com/retrio/gb-test-roms (Blargg's tests) and
+//! https://github.com/Gekkio/mooneye-test-suite when they've been downloaded.
+//! The ROMs aren't checked in so these tests skip themselves if the directory is missing.
+//! Point `GB_TEST_ROMS` or `MOONEYE_TEST_ROMS` at the directories to use somewhere else.
+//! The suites take a few minutes, set `SKIP_TEST_ROMS` to skip them even when they're there.
+
+use std::{
+    env, fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use rstest::rstest;
+
+use crate::{
+    headless::{run_headless, HeadlessOptions, HeadlessResult}, lcd::Lcd, serial::{NoPartner, Serial}, system::Emulator
+};
+
+/// Blargg's slowest ROMs take around a minute of emulated time
+const MAX_FRAMES: u64 = 60 * 120;
+
+/// Blargg suites that report over serial or at 0xA000 and test DMG hardware. The rest, like
+/// halt_bug and interrupt_time, only show their result on screen or need a CGB.
+const BLARGG_SUITES: [&str; 6] = ["cpu_instrs", "instr_timing", "mem_timing", "mem_timing-2", "dmg_sound", "oam_bug"];
+
+/// Mooneye directories that don't hold automated tests
+const MOONEYE_SKIPPED_DIRS: [&str; 3] = ["manual-only", "utils", "madness"];
+
+/// `variable` or a directory next to Cargo.toml if it isn't set
+fn rom_dir(variable: &str, default: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join(default))
+}
+
+/// Every .gb and .gbc file under `dir`, sorted so the report is in a stable order
+fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "gb" || ext == "gbc") {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+fn blargg_rom_included(relative_path: &Path) -> bool {
+    relative_path
+        .components()
+        .next()
+        .is_some_and(|suite| BLARGG_SUITES.iter().any(|name| suite.as_os_str() == *name))
+}
+
+/// Mooneye names ROMs that only pass on some models with a suffix like `-dmgABCmgb`, `-cgb` or
+/// `-GS` (G is the DMG family, S the SGB, C the CGB and A the AGB). Only the ones that expect
+/// the DMG this emulates after its boot ROM are run.
+fn mooneye_rom_included(relative_path: &Path) -> bool {
+    if relative_path
+        .components()
+        .any(|dir| MOONEYE_SKIPPED_DIRS.iter().any(|name| dir.as_os_str() == *name))
+    {
+        return false;
+    }
+
+    let stem = relative_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let Some((_, suffix)) = stem.rsplit_once('-') else {
+        return true;
+    };
+    let lowercase_models = ["dmg", "mgb", "sgb", "cgb", "agb", "ags"];
+    let uppercase_models = !suffix.is_empty() && suffix.chars().all(|c| "GSCA".contains(c));
+    if uppercase_models {
+        suffix.contains('G')
+    } else if lowercase_models.iter().any(|model| suffix.starts_with(model)) {
+        suffix.starts_with("dmgABC")
+    } else {
+        // Not a model suffix, just a name with a dash in it
+        true
+    }
+}
+
+/// Result of one ROM, or the panic message if the emulator crashed
+fn run_rom(path: &Path, options: &HeadlessOptions) -> Result<HeadlessResult, String> {
+    let rom = fs::read(path).map_err(|err| format!("couldn't read it: {}", err))?;
+    let mut emulator = Emulator::new(rom, None, Lcd::new(Default::default()), Serial::new(Box::new(NoPartner)))
+        .map_err(|err| format!("bad header: {}", err))?;
+
+    panic::catch_unwind(AssertUnwindSafe(|| run_headless(&mut emulator, options))).map_err(|err| {
+        err.downcast_ref::<String>()
+            .cloned()
+            .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "panicked".into())
+    })
+}
+
+/// Runs every included ROM in the suite, prints a line for each and fails if any of them
+/// didn't pass
+fn run_suite(name: &str, dir: &Path, included: fn(&Path) -> bool, options: &HeadlessOptions) {
+    if env::var_os("SKIP_TEST_ROMS").is_some() {
+        println!("Skipping {} test ROMs, SKIP_TEST_ROMS is set", name);
+        return;
+    }
+    if !dir.is_dir() {
+        println!("Skipping {} test ROMs, {} doesn't exist", name, dir.display());
+        return;
+    }
+
+    let roms: Vec<PathBuf> = find_roms(dir)
+        .into_iter()
+        .filter(|path| included(path.strip_prefix(dir).unwrap_or(path)))
+        .collect();
+    let mut failures = vec![];
+    for path in &roms {
+        let rom_name = path.strip_prefix(dir).unwrap_or(path).display();
+        match run_rom(path, options) {
+            Ok(HeadlessResult::Passed) => println!("PASS {}", rom_name),
+            Ok(result) => {
+                println!("FAIL {} ({:?})", rom_name, result);
+                failures.push(rom_name.to_string());
+            }
+            Err(err) => {
+                println!("FAIL {} ({})", rom_name, err);
+                failures.push(rom_name.to_string());
+            }
+        }
+    }
+
+    println!("{}: {}/{} passed", name, roms.len() - failures.len(), roms.len());
+    assert!(failures.is_empty(), "{} test ROMs failed: {:?}", name, failures);
+}
+
+#[test]
+fn blargg() {
+    let options = HeadlessOptions {
+        max_frames: Some(MAX_FRAMES),
+        pass_serial: Some("Passed".into()),
+        fail_serial: Some("Failed".into()),
+        blargg_memory: true,
+        ..Default::default()
+    };
+    run_suite("Blargg", &rom_dir("GB_TEST_ROMS", "gb-test-roms"), blargg_rom_included, &options);
+}
+
+#[test]
+fn mooneye() {
+    let options = HeadlessOptions {
+        max_frames: Some(MAX_FRAMES),
+        mooneye: true,
+        ..Default::default()
+    };
+    run_suite("Mooneye", &rom_dir("MOONEYE_TEST_ROMS", "mooneye-test-suite"), mooneye_rom_included, &options);
+}
+
+#[rstest]
+#[case("cpu_instrs/individual/01-special.gb", true)]
+#[case("dmg_sound/rom_singles/01-registers.gb", true)]
+#[case("cgb_sound/cgb_sound.gb", false)]
+#[case("halt_bug.gb", false)]
+fn blargg_rom_selection(#[case] path: &str, #[case] expected: bool) {
+    assert_eq!(expected, blargg_rom_included(Path::new(path)));
+}
+
+#[rstest]
+#[case("acceptance/add_sp_e_timing.gb", true)]
+#[case("acceptance/boot_regs-dmgABC.gb", true)]
+#[case("acceptance/boot_div-dmgABCmgb.gb", true)]
+#[case("acceptance/boot_hwio-G.gb", true)]
+#[case("acceptance/boot_div-dmg0.gb", false)]
+#[case("acceptance/boot_regs-mgb.gb", false)]
+#[case("acceptance/boot_div-S.gb", false)]
+#[case("misc/boot_regs-cgb.gb", false)]
+#[case("acceptance/boot_div2-S.gb", false)]
+#[case("acceptance/ppu/intr_2_mode0_timing_sprites.gb", true)]
+#[case("manual-only/sprite_priority.gb", false)]
+#[case("utils/dump_boot_hwio.gb", false)]
+fn mooneye_rom_selection(#[case] path: &str, #[case] expected: bool) {
+    assert_eq!(expected, mooneye_rom_included(Path::new(path)));
+}
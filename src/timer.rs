@@ -0,0 +1,195 @@
+use crate::{constants::*, memory::MemoryController};
+
+/// T-cycles between TIMA overflowing and it being reloaded from TMA
+const TIMA_RELOAD_DELAY: u8 = 4;
+
+// https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+pub struct Timer {
+    /// Internal 16 bit counter incremented every T-cycle. DIV is the upper 8 bits.
+    counter: u16,
+    /// Timer enable ANDed with the counter bit selected by TAC, from the previous cycle. TIMA
+    /// increments on the falling edge of this.
+    last_signal: bool,
+    /// Cycles left until TMA is loaded into TIMA after an overflow
+    reload_delay: Option<u8>,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            last_signal: false,
+            reload_delay: None,
+        }
+    }
+
+    /// Starts the internal counter where DIV reads the given value, like after the boot ROM
+    pub fn with_div(div: u8) -> Self {
+        Timer {
+            counter: (div as u16) << 8,
+            ..Timer::new()
+        }
+    }
+
+    /// Runs the timer for a single T-cycle
+    pub fn step(&mut self, mem: &mut dyn MemoryController) {
+        let shared_data = mem.shared_data_mut();
+        let div_written = std::mem::take(&mut shared_data.div_written);
+        let tima_written = std::mem::take(&mut shared_data.tima_written);
+
+        if let Some(delay) = self.reload_delay {
+            if delay == 1 {
+                // A TIMA write on this cycle is lost, TMA wins
+                mem.write_8_sys(ADDRESS_TIMA, mem.read_8_sys(ADDRESS_TMA));
+                mem.write_8_sys(ADDRESS_IF, mem.read_8_sys(ADDRESS_IF) | 4);
+                self.reload_delay = None;
+            } else if tima_written {
+                // Writing TIMA while it reads 0 after overflowing cancels the reload and interrupt
+                self.reload_delay = None;
+            } else {
+                self.reload_delay = Some(delay - 1);
+            }
+        }
+
+        if div_written {
+            self.counter = 0;
+        } else {
+            self.counter = self.counter.wrapping_add(1);
+        }
+
+        if self.counter & 0xFF == 0 {
+            mem.write_8_sys(ADDRESS_DIV, (self.counter >> 8) as u8);
+        }
+
+        // Resetting DIV or changing TAC can also cause a falling edge, which gives the spurious
+        // TIMA increments real hardware has
+        let tac = mem.read_8_sys(ADDRESS_TAC);
+        let signal = (tac & 4) != 0 && (self.counter & tac_counter_bit(tac)) != 0;
+        if self.last_signal && !signal {
+            self.increment_tima(mem);
+        }
+        self.last_signal = signal;
+    }
+
+    fn increment_tima(&mut self, mem: &mut dyn MemoryController) {
+        let tima = mem.read_8_sys(ADDRESS_TIMA);
+        if tima == 0xFF {
+            mem.write_8_sys(ADDRESS_TIMA, 0);
+            self.reload_delay = Some(TIMA_RELOAD_DELAY);
+        } else {
+            mem.write_8_sys(ADDRESS_TIMA, tima + 1);
+        }
+    }
+}
+
+/// Bit of the internal counter that clocks TIMA for the frequency selected in TAC
+fn tac_counter_bit(tac: u8) -> u16 {
+    match tac & 3 {
+        0 => 1 << 9,
+        1 => 1 << 3,
+        2 => 1 << 5,
+        3 => 1 << 7,
+        _ => panic!("Invalid timer clock select value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::*, memory::MemoryController, memory_controllers::basic_memory::BasicMemory};
+
+    use super::Timer;
+
+    fn step_n(timer: &mut Timer, mem: &mut dyn MemoryController, cycles: u32) {
+        for _ in 0..cycles {
+            timer.step(mem);
+        }
+    }
+
+    #[test]
+    fn div_increments_every_256_cycles() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+
+        step_n(&mut timer, &mut m, 255);
+        assert_eq!(0, m.read_8(ADDRESS_DIV));
+        step_n(&mut timer, &mut m, 1);
+        assert_eq!(1, m.read_8(ADDRESS_DIV));
+    }
+
+    #[test]
+    fn tima_increments_at_selected_frequency() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_TAC, 0b101);
+
+        step_n(&mut timer, &mut m, 16 * 3);
+        assert_eq!(3, m.read_8(ADDRESS_TIMA));
+    }
+
+    #[test]
+    fn tima_overflow_reloads_after_delay() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_TAC, 0b101);
+        m.write_8(ADDRESS_TMA, 0x42);
+        m.write_8(ADDRESS_TIMA, 0xFF);
+
+        step_n(&mut timer, &mut m, 16);
+        assert_eq!(0, m.read_8(ADDRESS_TIMA));
+        step_n(&mut timer, &mut m, 3);
+        assert_eq!(0, m.read_8(ADDRESS_TIMA));
+        assert_eq!(0, m.read_8(ADDRESS_IF) & 4);
+
+        step_n(&mut timer, &mut m, 1);
+        assert_eq!(0x42, m.read_8(ADDRESS_TIMA));
+        assert_ne!(0, m.read_8(ADDRESS_IF) & 4);
+    }
+
+    #[test]
+    fn tima_write_during_reload_delay_cancels_reload() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_TAC, 0b101);
+        m.write_8(ADDRESS_TMA, 0x42);
+        m.write_8(ADDRESS_TIMA, 0xFF);
+
+        step_n(&mut timer, &mut m, 17);
+        m.write_8(ADDRESS_TIMA, 0x10);
+        step_n(&mut timer, &mut m, 4);
+
+        assert_eq!(0x10, m.read_8(ADDRESS_TIMA));
+        assert_eq!(0, m.read_8(ADDRESS_IF) & 4);
+    }
+
+    #[test]
+    fn tima_write_on_reload_cycle_is_lost() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_TAC, 0b101);
+        m.write_8(ADDRESS_TMA, 0x42);
+        m.write_8(ADDRESS_TIMA, 0xFF);
+
+        step_n(&mut timer, &mut m, 19);
+        m.write_8(ADDRESS_TIMA, 0x10);
+        step_n(&mut timer, &mut m, 1);
+
+        assert_eq!(0x42, m.read_8(ADDRESS_TIMA));
+        assert_ne!(0, m.read_8(ADDRESS_IF) & 4);
+    }
+
+    #[test]
+    fn div_write_causes_falling_edge() {
+        let mut m = BasicMemory::default();
+        let mut timer = Timer::new();
+        m.write_8(ADDRESS_TAC, 0b101);
+
+        // bit 3 of the counter is now set
+        step_n(&mut timer, &mut m, 8);
+        assert_eq!(0, m.read_8(ADDRESS_TIMA));
+
+        m.write_8(ADDRESS_DIV, 0x12);
+        step_n(&mut timer, &mut m, 1);
+        assert_eq!(1, m.read_8(ADDRESS_TIMA));
+        assert_eq!(0, m.read_8(ADDRESS_DIV));
+    }
+}